@@ -0,0 +1,145 @@
+use best_analysis::*;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use indicatif::ProgressBar;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::RwLock;
+
+/// Number of characters in synthetic corpus. Override with `BENCH_CHARS` environment variable.
+const DEFAULT_CHARS: usize = 20_000;
+/// Seed of synthetic corpus. Override with `BENCH_SEED` environment variable.
+const DEFAULT_SEED: u64 = 42;
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+/// Deterministic xorshift generator so the same seed always produce the same corpus.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self, bound: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % bound
+    }
+}
+
+/// Document in the shape of JSON corpus where each word is an array of characters and tag.
+type SyntheticDocument = Vec<Vec<(Vec<char>, Tag)>>;
+
+/// Generate a corpus of about `chars` Thai characters from `seed`.
+fn synthetic_corpus(chars: usize, seed: u64) -> Vec<SyntheticDocument> {
+    let mut rng = XorShift(seed.max(1));
+    let mut corpus = vec![];
+    let mut total = 0;
+    while total < chars {
+        let doc: SyntheticDocument = (0..(1 + rng.next(4))).map(|_| {
+            (0..(1 + rng.next(12))).map(|_| {
+                let word: Vec<char> = (0..(1 + rng.next(6))).map(|_| {
+                    std::char::from_u32(0x0E01 + rng.next(46) as u32).unwrap()
+                }).collect();
+                total += word.len();
+                (word, 1 + rng.next(4) as Tag)
+            }).collect()
+        }).collect();
+        corpus.push(doc);
+    }
+
+    corpus
+}
+
+/// Write synthetic corpus to a JSON file in temp directory.
+fn synthetic_corpus_file(chars: usize, seed: u64) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("best_analysis_bench_{}_{}.json", chars, seed));
+    let corpus = synthetic_corpus(chars, seed);
+    let file = std::fs::File::create(&path).expect("Fail to create synthetic corpus");
+    serde_json::to_writer(std::io::BufWriter::new(file), &corpus).expect("Fail to write synthetic corpus");
+
+    path
+}
+
+fn vectorize_option() -> VectorizeOption {
+    VectorizeOption {
+        char_include_list: vec![],
+        unicode_ranges: parse_unicode_ranges("0E01..0E7F").unwrap(),
+        equiv_classes: EquivClasses::default(),
+        oov_mode: OovMode::KeepPosition,
+        normalization: Normalization::None,
+        fold_case: false,
+        id_width: IdWidth::U32,
+        tag_width: TagWidth::U8,
+        tag_map: HashMap::new(),
+        tag_filter: TagFilter::All,
+        sample: None,
+        doc_range: None
+    }
+}
+
+fn vectorize_corpus(corpuses: &[PathBuf]) -> Vectorized {
+    let read = ReadOption {buf_size: 16 * 1024 * 1024, format: InputFormat::Json, encoding: Encoding::Utf8, strict: true};
+    let map = RwLock::new(HashMap::new());
+    let init = RwLock::new(1);
+    vectorize(read, &vectorize_option(), corpuses, &init, &map, VectorizeControl {progress: &ProgressBar::hidden(), interrupted: &AtomicBool::new(false)}, None).unwrap()
+}
+
+fn bench_vectorize(c: &mut Criterion) {
+    let chars = env_or("BENCH_CHARS", DEFAULT_CHARS);
+    let corpuses = vec![synthetic_corpus_file(chars, env_or("BENCH_SEED", DEFAULT_SEED))];
+    let total = vectorize_corpus(&corpuses).tagged.len();
+
+    let mut group = c.benchmark_group("vectorize");
+    group.throughput(Throughput::Elements(total as u64));
+    group.bench_function(BenchmarkId::from_parameter(total), |b| b.iter(|| vectorize_corpus(&corpuses)));
+    group.finish();
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let chars = env_or("BENCH_CHARS", DEFAULT_CHARS);
+    let corpuses = vec![synthetic_corpus_file(chars, env_or("BENCH_SEED", DEFAULT_SEED))];
+    let read = ReadOption {buf_size: 16 * 1024 * 1024, format: InputFormat::Json, encoding: Encoding::Utf8, strict: true};
+
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Elements(chars as u64));
+    group.bench_function(BenchmarkId::from_parameter(chars), |b| b.iter(|| count_documents(read, &corpuses)));
+    group.finish();
+}
+
+fn bench_unique_vecs_idx(c: &mut Criterion) {
+    let chars = env_or("BENCH_CHARS", DEFAULT_CHARS);
+    let corpuses = vec![synthetic_corpus_file(chars, env_or("BENCH_SEED", DEFAULT_SEED))];
+    let vecs: Vec<Id> = vectorize_corpus(&corpuses).tagged.into_iter().map(|(v, _)| v).collect();
+    let window = WindowOption {stride: 1, sentence_lens: None};
+
+    let mut group = c.benchmark_group("get_unique_vecs_idx");
+    group.throughput(Throughput::Elements(vecs.len() as u64));
+    for gram in 1..=5u8 {
+        let flatten = sorted_windows(gram, &vecs, window_starts(gram as usize, vecs.len(), &window));
+        group.bench_with_input(BenchmarkId::from_parameter(gram), &flatten, |b, flatten| {
+            b.iter(|| get_unique_vecs_idx(gram, &vecs, flatten))
+        });
+    }
+    group.finish();
+}
+
+fn bench_sorted_windows(c: &mut Criterion) {
+    let chars = env_or("BENCH_CHARS", DEFAULT_CHARS);
+    let corpuses = vec![synthetic_corpus_file(chars, env_or("BENCH_SEED", DEFAULT_SEED))];
+    let vecs: Vec<Id> = vectorize_corpus(&corpuses).tagged.into_iter().map(|(v, _)| v).collect();
+    let window = WindowOption {stride: 1, sentence_lens: None};
+
+    let mut group = c.benchmark_group("sorted_windows");
+    group.throughput(Throughput::Elements(vecs.len() as u64));
+    for gram in 1..=5u8 {
+        let starts = window_starts(gram as usize, vecs.len(), &window);
+        group.bench_with_input(BenchmarkId::from_parameter(gram), &starts, |b, starts| {
+            b.iter(|| sorted_windows(gram, &vecs, starts.clone()))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_vectorize, bench_parse, bench_unique_vecs_idx, bench_sorted_windows);
+criterion_main!(benches);
@@ -0,0 +1,5119 @@
+use glob::glob;
+use indicatif::ProgressBar;
+use log::{debug, info, warn, Level};
+use rayon::prelude::*;
+use serde::de::{Deserialize, Deserializer, Error as DeError, SeqAccess, Unexpected, Visitor};
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
+use std::fmt;
+use std::hash::Hash;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::iter::Iterator;
+use std::ops::{Range, RangeInclusive};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use unbytify::{bytify, unbytify};
+use unicode_normalization::UnicodeNormalization;
+
+pub type Document = Vec<    // Sentences
+    Vec<                    // Words
+        (
+            String,         // characters
+            Tag             // tag
+        )
+    >
+>;
+
+/// Statistic of a single corpus file.
+pub struct FileStats {
+    pub path: PathBuf,
+    pub documents: usize,
+    pub sentences: usize,
+    pub words: usize,
+    pub chars: usize,
+    /// Time spent reading, deserializing, and vectorizing the file.
+    pub parse_time: Duration,
+    /// Part of `parse_time` spent vectorizing deserialized documents.
+    pub vectorize_time: Duration
+}
+
+/// Vectorized corpus.
+#[derive(Default)]
+pub struct Vectorized {
+    /// Pair of vectorized char and tag.
+    pub tagged: Vec<(Id, Tag)>,
+    /// Length of each sentence in the same order as `tagged`.
+    pub sentence_lens: Vec<usize>,
+    /// Length of each document in the same order as `tagged`.
+    pub document_lens: Vec<usize>,
+    /// Index of each document within its corpus file in the same order as `document_lens`.
+    /// It differ from position of document when documents are sampled or limited by range.
+    pub document_indices: Vec<usize>,
+    /// Statistic of each corpus file in the same order as `tagged`.
+    pub file_stats: Vec<FileStats>,
+    /// Number of words of each length in vectorized characters.
+    pub word_lens: HashMap<usize, usize>
+}
+
+/// Vectorized char.
+pub type Id = u32;
+
+/// Number of bits that vectorized char is allowed to use.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IdWidth {
+    U8,
+    U16,
+    U32
+}
+
+impl IdWidth {
+    /// Largest vectorized char of this width. It is reserved for oov token.
+    fn max(self) -> Id {
+        match self {
+            IdWidth::U8 => Id::from(u8::MAX),
+            IdWidth::U16 => Id::from(u16::MAX),
+            IdWidth::U32 => u32::MAX
+        }
+    }
+}
+
+impl FromStr for IdWidth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<IdWidth, String> {
+        match s {
+            "u8" => Ok(IdWidth::U8),
+            "u16" => Ok(IdWidth::U16),
+            "u32" => Ok(IdWidth::U32),
+            _ => Err(format!("Unknown id width {}", s))
+        }
+    }
+}
+
+impl fmt::Display for IdWidth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IdWidth::U8 => f.write_str("u8"),
+            IdWidth::U16 => f.write_str("u16"),
+            IdWidth::U32 => f.write_str("u32")
+        }
+    }
+}
+
+/// Tag of word.
+pub type Tag = u16;
+
+/// Number of bits that tag in corpus is allowed to use.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TagWidth {
+    U8,
+    U16
+}
+
+impl TagWidth {
+    /// Largest tag of this width.
+    pub fn max(self) -> Tag {
+        match self {
+            TagWidth::U8 => Tag::from(u8::MAX),
+            TagWidth::U16 => u16::MAX
+        }
+    }
+}
+
+impl FromStr for TagWidth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TagWidth, String> {
+        match s {
+            "u8" => Ok(TagWidth::U8),
+            "u16" => Ok(TagWidth::U16),
+            _ => Err(format!("Unknown tag width {}", s))
+        }
+    }
+}
+
+impl fmt::Display for TagWidth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TagWidth::U8 => f.write_str("u8"),
+            TagWidth::U16 => f.write_str("u16")
+        }
+    }
+}
+
+/// How to vectorize a character that is neither in unicode range nor include list.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OovMode {
+    /// Remove the character from vectorized corpus entirely.
+    Drop,
+    /// Vectorize the character to the largest id of `IdWidth`.
+    Token,
+    /// Vectorize the character to 0.
+    KeepPosition
+}
+
+impl OovMode {
+    /// Vectorized char that excluded character become under this mode with given `id_width`.
+    /// None if excluded character is dropped.
+    pub fn oov_id(self, id_width: IdWidth) -> Option<Id> {
+        match self {
+            OovMode::Drop => None,
+            OovMode::Token => Some(id_width.max()),
+            OovMode::KeepPosition => Some(0)
+        }
+    }
+}
+
+impl FromStr for OovMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OovMode, String> {
+        match s {
+            "drop" => Ok(OovMode::Drop),
+            "token" => Ok(OovMode::Token),
+            "keep-position" => Ok(OovMode::KeepPosition),
+            _ => Err(format!("Unknown oov mode {}", s))
+        }
+    }
+}
+
+/// Which boundaries of corpus an n-gram window is allowed to cross.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Boundaries {
+    /// Flatten the whole corpus into one sequence so windows cross every boundary.
+    Ignore,
+    /// Windows never cross sentence boundary.
+    Sentence,
+    /// Windows never cross document boundary but may cross sentence boundary.
+    Document
+}
+
+impl Boundaries {
+    /// Select length of each segment that windows must stay inside of.
+    /// None if windows may cross every boundary.
+    pub fn segment_lens<'a>(self, sentence_lens: &'a [usize], document_lens: &'a [usize]) -> Option<&'a [usize]> {
+        match self {
+            Boundaries::Ignore => None,
+            Boundaries::Sentence => Some(sentence_lens),
+            Boundaries::Document => Some(document_lens)
+        }
+    }
+}
+
+impl FromStr for Boundaries {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Boundaries, String> {
+        match s {
+            "ignore" => Ok(Boundaries::Ignore),
+            "sentence" => Ok(Boundaries::Sentence),
+            "document" => Ok(Boundaries::Document),
+            _ => Err(format!("Unknown boundaries {}", s))
+        }
+    }
+}
+
+impl fmt::Display for Boundaries {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Boundaries::Ignore => f.write_str("ignore"),
+            Boundaries::Sentence => f.write_str("sentence"),
+            Boundaries::Document => f.write_str("document")
+        }
+    }
+}
+
+/// Unicode normalization form applied to each word before it is vectorized.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Normalization {
+    None,
+    Nfc,
+    Nfd
+}
+
+impl FromStr for Normalization {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Normalization, String> {
+        match s {
+            "none" => Ok(Normalization::None),
+            "nfc" => Ok(Normalization::Nfc),
+            "nfd" => Ok(Normalization::Nfd),
+            _ => Err(format!("Unknown normalization {}", s))
+        }
+    }
+}
+
+/// Which words are vectorized according to their tag.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TagFilter {
+    /// Every word is vectorized.
+    All,
+    /// Only words whose tag is in the list are vectorized.
+    Only(Vec<Tag>),
+    /// Words whose tag is in the list are dropped.
+    Exclude(Vec<Tag>)
+}
+
+impl TagFilter {
+    /// Return true if word with `tag` should be vectorized.
+    fn accept(&self, tag: Tag) -> bool {
+        match self {
+            TagFilter::All => true,
+            TagFilter::Only(tags) => tags.contains(&tag),
+            TagFilter::Exclude(tags) => !tags.contains(&tag)
+        }
+    }
+}
+
+/// Parse comma separated list of tag, e.g. `1,4,7`.
+pub fn parse_tags(tags: &str) -> Result<Vec<Tag>, String> {
+    tags.split(',').map(|t| {
+        t.trim().parse::<Tag>().map_err(|_| format!("Tag must be a number in range 0..65535 but found \"{}\"", t))
+    }).collect()
+}
+
+/// Parse a line of tag-map-file in form `old=new`, e.g. `5=4`, into pair of old and new tag.
+/// Blank line and comment line which start with `#` contribute nothing.
+/// Tag cannot be mapped to 0 since 0 mark a character that is not the last of its word.
+pub fn parse_tag_map_line(line: &str) -> Result<Option<(Tag, Tag)>, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None)
+    }
+    let mut tags = line.splitn(2, '=').map(|t| t.trim().parse::<Tag>());
+    match (tags.next(), tags.next()) {
+        (Some(Ok(_)), Some(Ok(0))) => Err(format!("Tag cannot be mapped to 0 in tag-map-file line \"{}\"", line)),
+        (Some(Ok(old)), Some(Ok(new))) => Ok(Some((old, new))),
+        _ => Err(format!("Tag-map-file line must be old=new with tag in range 0..65535 but found \"{}\"", line))
+    }
+}
+
+/// Characters that share a single vectorized char, e.g. all digits.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EquivClass {
+    /// Name of the class which is recorded in char map.
+    pub name: String,
+    /// Member characters of the class. The first one represent the class in char map.
+    pub chars: Vec<char>
+}
+
+/// Parse equivalence class in form `CHARS=NAME`, e.g. `0123456789=DIGIT`.
+/// Repeated character in `CHARS` is counted once.
+pub fn parse_equiv_class(class: &str) -> Result<EquivClass, String> {
+    let mut parts = class.rsplitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(name), Some(chars)) if !name.trim().is_empty() && !chars.is_empty() => {
+            let mut members: Vec<char> = vec![];
+            for ch in chars.chars() {
+                if !members.contains(&ch) {
+                    members.push(ch);
+                }
+            }
+            Ok(EquivClass {name: name.trim().to_owned(), chars: members})
+        },
+        _ => Err(format!("Equivalence class must be CHARS=NAME but found \"{}\"", class))
+    }
+}
+
+/// Set of equivalence classes where each character belong to at most one class.
+#[derive(Clone, Debug, Default)]
+pub struct EquivClasses {
+    classes: Vec<EquivClass>,
+    class_of: HashMap<char, usize>
+}
+
+impl EquivClasses {
+    /// Build set of `classes`.
+    /// 
+    /// # Return
+    /// Err if a character is a member of more than one class or two classes have the same name.
+    pub fn new(classes: Vec<EquivClass>) -> Result<EquivClasses, String> {
+        let mut class_of = HashMap::new();
+        for (i, class) in classes.iter().enumerate() {
+            if classes[..i].iter().any(|c| c.name == class.name) {
+                return Err(format!("Equivalence class {} is given more than once", class.name))
+            }
+            for ch in &class.chars {
+                if let Some(j) = class_of.insert(*ch, i) {
+                    return Err(format!("Character {:?} belong to both equivalence class {} and {}", ch, classes[j].name, class.name))
+                }
+            }
+        }
+
+        Ok(EquivClasses {classes, class_of})
+    }
+
+    /// Return true if there is no class.
+    pub fn is_empty(&self) -> bool {
+        self.classes.is_empty()
+    }
+
+    /// The class that `ch` belong to, if any.
+    pub fn class_of(&self, ch: char) -> Option<&EquivClass> {
+        self.class_of.get(&ch).map(|i| &self.classes[*i])
+    }
+
+    /// The character that represent class of `ch` in char map, if `ch` belong to any class.
+    pub fn representative(&self, ch: char) -> Option<char> {
+        self.class_of(ch).map(|class| class.chars[0])
+    }
+}
+
+/// Deterministic random sample of documents.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sample {
+    /// Fraction of documents to be kept, in range (0, 1].
+    pub fraction: f64,
+    /// Seed that decide which documents are kept.
+    pub seed: u64
+}
+
+impl Sample {
+    /// Return true if document at `doc_idx` of corpus file `path` is in the sample.
+    /// The decision only depend on `seed`, `path` and `doc_idx` so the same documents
+    /// are always kept regardless of order that files are read.
+    pub fn keep(&self, path: &Path, doc_idx: usize) -> bool {
+        // FNV-1a of path then splitmix64 finalizer so hash is stable across build
+        let path_hash = path.to_string_lossy().bytes().fold(0xcbf2_9ce4_8422_2325u64, |h, b| (h ^ b as u64).wrapping_mul(0x100_0000_01b3));
+        let mut x = self.seed ^ path_hash ^ (doc_idx as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        x ^= x >> 31;
+
+        ((x >> 11) as f64 / (1u64 << 53) as f64) < self.fraction
+    }
+}
+
+/// Range of documents to be vectorized by their index across every corpus file in order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DocRange {
+    /// Half-open range of global document index.
+    pub range: Range<usize>,
+    /// Global index of first document of each corpus file.
+    pub file_offsets: HashMap<PathBuf, usize>
+}
+
+impl DocRange {
+    /// Create document range over `corpuses` where `document_counts` is number of documents
+    /// of each of `corpuses`, as returned by [count_documents](fn.count_documents.html).
+    pub fn new(range: Range<usize>, corpuses: &[PathBuf], document_counts: &[usize]) -> DocRange {
+        let mut offset = 0;
+        let file_offsets = corpuses.iter().zip(document_counts).map(|(f, count)| {
+            let file_offset = offset;
+            offset += count;
+            (f.to_owned(), file_offset)
+        }).collect();
+
+        DocRange {range, file_offsets}
+    }
+
+    /// Return true if document at `doc_idx` of corpus file `path` is in range.
+    /// Document of file that is not one of corpuses of this range is never kept.
+    pub fn keep(&self, path: &Path, doc_idx: usize) -> bool {
+        self.file_offsets.get(path).map(|offset| self.range.contains(&(offset + doc_idx))).unwrap_or(false)
+    }
+}
+
+/// Parse document range in `START:END` form. END must be larger than START.
+pub fn parse_doc_range(range: &str) -> Result<Range<usize>, String> {
+    let invalid = || format!("Document range must be START:END where START < END but found \"{}\"", range);
+    let (start, end) = range.split_once(':').ok_or_else(invalid)?;
+    match (start.trim().parse::<usize>(), end.trim().parse::<usize>()) {
+        (Ok(start), Ok(end)) if start < end => Ok(start..end),
+        _ => Err(invalid())
+    }
+}
+
+/// Parse sampling fraction. It must be in range (0, 1].
+pub fn parse_sample_fraction(fraction: &str) -> Result<f64, String> {
+    match fraction.trim().parse::<f64>() {
+        Ok(f) if f > 0f64 && f <= 1f64 => Ok(f),
+        _ => Err(format!("Sample fraction must be a number in range (0, 1] but found \"{}\"", fraction))
+    }
+}
+
+/// Options that control how corpus files are read.
+#[derive(Clone, Copy)]
+pub struct ReadOption {
+    /// Buffer size when reading corpus.
+    pub buf_size: usize,
+    /// Format of every corpus file.
+    pub format: InputFormat,
+    /// Text encoding of every corpus file.
+    pub encoding: Encoding,
+    /// If true, corpus file that cannot be opened or parsed abort vectorization. Otherwise, it is skipped.
+    pub strict: bool
+}
+
+/// Options that control which character get vectorized and how.
+#[derive(Clone)]
+pub struct VectorizeOption {
+    /// Non-Thai characters to be vectorized.
+    pub char_include_list: Vec<char>,
+    /// Unicode codepoint ranges to be vectorized.
+    pub unicode_ranges: Vec<RangeInclusive<u32>>,
+    /// Characters in the same class share one vectorized char. Members are always vectorized.
+    pub equiv_classes: EquivClasses,
+    /// How to vectorize character that is excluded.
+    pub oov_mode: OovMode,
+    /// Unicode normalization applied to each word before it is vectorized.
+    pub normalization: Normalization,
+    /// If true, case of vectorized character is folded so upper and lower case share vectorized char.
+    pub fold_case: bool,
+    /// Number of bits that vectorized char is allowed to use.
+    pub id_width: IdWidth,
+    /// Number of bits that tag in corpus is allowed to use.
+    pub tag_width: TagWidth,
+    /// Mapping of tag in corpus to tag to be used in analysis. Tag that is not in the map is used as is.
+    pub tag_map: HashMap<Tag, Tag>,
+    /// Words to be vectorized according to their mapped tag.
+    pub tag_filter: TagFilter,
+    /// If it is given, only documents in the sample are vectorized.
+    pub sample: Option<Sample>,
+    /// If it is given, only documents in the range are vectorized.
+    pub doc_range: Option<DocRange>
+}
+
+impl VectorizeOption {
+    /// Return true if document at `doc_idx` of corpus file `path` is to be vectorized.
+    /// It must be in both sample and document range if they are given.
+    pub fn keep_document(&self, path: &Path, doc_idx: usize) -> bool {
+        self.sample.map(|sample| sample.keep(path, doc_idx)).unwrap_or(true)
+            && self.doc_range.as_ref().map(|range| range.keep(path, doc_idx)).unwrap_or(true)
+    }
+}
+
+/// A serde visitor that deserialize top level array of corpus one document at a time.
+/// Each document is passed to `consume` then discarded so the entire corpus
+/// never need to be resident in memory.
+struct DocumentVisitor<F> where F: FnMut(Document) -> Result<(), String> {
+    consume: F
+}
+
+impl<'de, F> Visitor<'de> for DocumentVisitor<F> where F: FnMut(Document) -> Result<(), String> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of documents")
+    }
+
+    fn visit_seq<A>(mut self, mut seq: A) -> Result<(), A::Error> where A: SeqAccess<'de> {
+        while let Some(doc) = seq.next_element::<Vec<Vec<Word>>>()? {
+            let doc = doc.into_iter().map(|sentence| sentence.into_iter().map(|Word(chars, tag)| (chars, tag)).collect()).collect();
+            (self.consume)(doc).map_err(A::Error::custom)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A word of corpus. It is deserialized from either a pair of characters and tag,
+/// e.g. `[["ก", "ข"], 1]`, or bare characters without tag, e.g. `["ก", "ข"]`, in which case tag is 0.
+/// Characters are collected into a single `String` so each word is a single compact allocation.
+struct Word(String, Tag);
+
+/// First element of a word. It is characters of tagged word or first character of untagged word.
+enum WordHead {
+    Chars(String),
+    Char(char)
+}
+
+struct WordHeadVisitor;
+
+impl<'de> Visitor<'de> for WordHeadVisitor {
+    type Value = WordHead;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of characters or a character")
+    }
+
+    fn visit_char<E>(self, ch: char) -> Result<WordHead, E> where E: DeError {
+        Ok(WordHead::Char(ch))
+    }
+
+    fn visit_str<E>(self, s: &str) -> Result<WordHead, E> where E: DeError {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(ch), None) => Ok(WordHead::Char(ch)),
+            _ => Err(E::invalid_value(Unexpected::Str(s), &"a character"))
+        }
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<WordHead, A::Error> where A: SeqAccess<'de> {
+        let mut chars = String::new();
+        while let Some(ch) = seq.next_element::<char>()? {
+            chars.push(ch);
+        }
+
+        Ok(WordHead::Chars(chars))
+    }
+}
+
+impl<'de> Deserialize<'de> for WordHead {
+    fn deserialize<D>(deserializer: D) -> Result<WordHead, D::Error> where D: Deserializer<'de> {
+        deserializer.deserialize_any(WordHeadVisitor)
+    }
+}
+
+struct WordVisitor;
+
+impl<'de> Visitor<'de> for WordVisitor {
+    type Value = Word;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a pair of array of characters and tag or an array of characters")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Word, A::Error> where A: SeqAccess<'de> {
+        match seq.next_element::<WordHead>()? {
+            Some(WordHead::Chars(chars)) => {
+                let tag = seq.next_element::<Tag>()?.ok_or_else(|| A::Error::invalid_length(1, &self))?;
+                if seq.next_element::<serde::de::IgnoredAny>()?.is_some() {
+                    return Err(A::Error::invalid_length(3, &self))
+                }
+                Ok(Word(chars, tag))
+            },
+            Some(WordHead::Char(ch)) => {
+                let mut chars = ch.to_string();
+                while let Some(ch) = seq.next_element::<char>()? {
+                    chars.push(ch);
+                }
+                Ok(Word(chars, 0))
+            },
+            None => Ok(Word(String::new(), 0))
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Word {
+    fn deserialize<D>(deserializer: D) -> Result<Word, D::Error> where D: Deserializer<'de> {
+        deserializer.deserialize_seq(WordVisitor)
+    }
+}
+
+/// Read a corpus from `reader` and call `consume` on each document as soon as
+/// it is deserialized. If `consume` return Err, reading stop and the error is returned.
+fn for_each_document<R, F>(reader: R, consume: F) -> serde_json::Result<()> where R: Read, F: FnMut(Document) -> Result<(), String> {
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    deserializer.deserialize_seq(DocumentVisitor {consume})?;
+    deserializer.end()
+}
+
+/// Format of corpus file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputFormat {
+    /// Nested JSON array of document, sentence, word and character.
+    Json,
+    /// Original BEST text where each line is a sentence, word is terminated by `|`
+    /// and special word is enclosed in tag, e.g. `<NE>word</NE>|`.
+    BestText
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<InputFormat, String> {
+        match s {
+            "json" => Ok(InputFormat::Json),
+            "best-text" => Ok(InputFormat::BestText),
+            _ => Err(format!("Unknown input format {}", s))
+        }
+    }
+}
+
+/// Text encoding of corpus file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// Thai Industrial Standard 620. It is decoded as its superset, Windows-874.
+    Tis620
+}
+
+impl Encoding {
+    /// Encoding to transcode corpus from. None if corpus is already UTF-8.
+    fn transcode_from(self) -> Option<&'static encoding_rs::Encoding> {
+        match self {
+            Encoding::Utf8 => None,
+            Encoding::Utf16Le => Some(encoding_rs::UTF_16LE),
+            Encoding::Utf16Be => Some(encoding_rs::UTF_16BE),
+            Encoding::Tis620 => Some(encoding_rs::WINDOWS_874)
+        }
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Encoding, String> {
+        match s {
+            "utf8" => Ok(Encoding::Utf8),
+            "utf16le" => Ok(Encoding::Utf16Le),
+            "utf16be" => Ok(Encoding::Utf16Be),
+            "tis620" => Ok(Encoding::Tis620),
+            _ => Err(format!("Unknown encoding {}", s))
+        }
+    }
+}
+
+/// Map BEST text tag name to word tag.
+/// 
+/// | tag            | word tag |
+/// |----------------|----------|
+/// | no tag         | 1        |
+/// | `NE` (name)    | 2        |
+/// | `AB` (abbrev.) | 3        |
+/// | `POEM`         | 4        |
+fn best_text_tag(name: &str) -> Option<Tag> {
+    match name {
+        "NE" => Some(2),
+        "AB" => Some(3),
+        "POEM" => Some(4),
+        _ => None
+    }
+}
+
+/// Parse BEST text from `reader` into a single document.
+/// Each non-empty line is a sentence. Each word is terminated by `|`.
+/// Trailing whitespace after the last word of a line is ignored.
+/// A tag open by `<TAG>` apply to every word until it is closed by `</TAG>`.
+/// Tag is mapped to word tag by [best_text_tag](fn.best_text_tag.html).
+/// 
+/// # Return
+/// Err with line number if text contain unknown or unbalanced tag.
+pub fn parse_best_text<R>(reader: R) -> Result<Document, String> where R: BufRead {
+    let mut doc = Document::new();
+    let mut open_tag: Option<(String, Tag)> = None;
+
+    for (line_idx, line) in reader.lines().enumerate() {
+        let line = line.map_err(|err| err.to_string())?;
+        let line = line.trim_end();
+        let mut sentence = vec![];
+
+        for mut word in line.split('|') {
+            if let Some(rest) = word.strip_prefix('<').filter(|r| !r.starts_with('/')) {
+                let end = rest.find('>').ok_or_else(|| format!("line {}: unclosed tag in \"{}\"", line_idx + 1, word))?;
+                let name = &rest[..end];
+                if let Some((open, _)) = &open_tag {
+                    return Err(format!("line {}: tag <{}> open inside <{}>", line_idx + 1, name, open))
+                }
+                let tag = best_text_tag(name).ok_or_else(|| format!("line {}: unknown tag <{}>", line_idx + 1, name))?;
+                open_tag = Some((name.to_owned(), tag));
+                word = &rest[end + 1..];
+            }
+            let mut close = false;
+            if let Some(start) = word.rfind("</") {
+                let name = word[start + 2..].trim_end_matches('>');
+                match &open_tag {
+                    Some((open, _)) if open == name => close = true,
+                    _ => return Err(format!("line {}: unexpected closing tag </{}>", line_idx + 1, name))
+                }
+                word = &word[..start];
+            }
+            if !word.is_empty() {
+                sentence.push((word.to_owned(), open_tag.as_ref().map(|(_, t)| *t).unwrap_or(1)));
+            }
+            if close {
+                open_tag = None;
+            }
+        }
+
+        if !sentence.is_empty() {
+            doc.push(sentence);
+        }
+    }
+
+    if let Some((open, _)) = open_tag {
+        return Err(format!("tag <{}> is never closed", open))
+    }
+
+    Ok(doc)
+}
+
+/// Explanation of expected JSON corpus shape appended to error of wrongly shaped corpus.
+const CORPUS_SHAPE_HINT: &str = "Corpus must be an array of documents. Each document is an array of sentences. \
+Each sentence is an array of words. Each word is a pair of array of characters and tag, e.g. [[[[[\"ก\", \"ข\"], 1]]]], \
+or an array of characters without tag, e.g. [[[[\"ก\", \"ข\"]]]]";
+
+/// Read a corpus of given `format` from `reader` and call `consume` on each document.
+/// BEST text is a single document while JSON corpus is streamed one document at a time.
+/// 
+/// # Return
+/// Err returned by `consume` as is or Err describing why corpus cannot be parsed.
+/// If JSON corpus is valid JSON but has wrong shape, the error also explain the expected shape.
+pub fn for_each_corpus_document<R, F>(reader: R, format: InputFormat, mut consume: F) -> Result<(), String> where R: Read, F: FnMut(Document) -> Result<(), String> {
+    match format {
+        InputFormat::Json => {
+            let mut consume_err = None;
+            let result = for_each_document(reader, |doc| {
+                consume(doc).inspect_err(|err| consume_err = Some(err.clone()))
+            });
+            match (result, consume_err) {
+                (Ok(()), _) => Ok(()),
+                (Err(_), Some(err)) => Err(err),
+                (Err(err), None) if err.classify() == serde_json::error::Category::Data => Err(format!("{}. {}", err, CORPUS_SHAPE_HINT)),
+                (Err(err), None) => Err(err.to_string())
+            }
+        },
+        InputFormat::BestText => consume(parse_best_text(BufReader::new(reader))?)
+    }
+}
+
+/// Parse comma separated list of hex unicode range in `START..END` form.
+/// Both `START` and `END` are inclusive, e.g. `0E01..0E7F,0E80..0EFF`.
+pub fn parse_unicode_ranges(ranges: &str) -> Result<Vec<RangeInclusive<u32>>, String> {
+    ranges.split(',').map(|range| {
+        let mut bounds = range.trim().splitn(2, "..");
+        let mut parse = |name: &str| {
+            let bound = bounds.next().unwrap_or("").trim();
+            u32::from_str_radix(bound.trim_start_matches("0x"), 16).map_err(|_| {
+                format!("Invalid {} of unicode range \"{}\"", name, range)
+            })
+        };
+        let start = parse("START")?;
+        let end = parse("END")?;
+        if start > end {
+            return Err(format!("START is greater than END in unicode range \"{}\"", range))
+        }
+
+        Ok(start..=end)
+    }).collect()
+}
+
+/// Parse a line of char-list-file into characters.
+/// A line can be either a single character, a range of character such as `a-z`,
+/// or a range of hex codepoint such as `0030-0039`. Both ends of range are inclusive.
+/// Any other line contribute only its first character.
+/// Blank line and comment line which start with `#` contribute nothing. Use `0023-0023` to include `#`.
+/// Line that start with whitespace also contribute nothing and a warning is printed.
+pub fn parse_char_list_line(line: &str) -> Result<Vec<char>, String> {
+    if line.trim().is_empty() || line.starts_with('#') {
+        return Ok(vec![])
+    }
+    if line.starts_with(char::is_whitespace) {
+        warn!("Char-list-file line \"{}\" start with whitespace. It is skipped.", line);
+        return Ok(vec![])
+    }
+    let chars: Vec<char> = line.chars().collect();
+    let range = if chars.len() == 3 && chars[1] == '-' {
+        Some((chars[0] as u32, chars[2] as u32))
+    } else {
+        let mut bounds = line.splitn(2, '-');
+        match (bounds.next().map(|b| u32::from_str_radix(b, 16)), bounds.next().map(|b| u32::from_str_radix(b, 16))) {
+            (Some(Ok(start)), Some(Ok(end))) => Some((start, end)),
+            _ => None
+        }
+    };
+
+    match range {
+        Some((start, end)) if start > end => Err(format!("Start of range is greater than its end in char-list-file line \"{}\"", line)),
+        Some((start, end)) => Ok((start..=end).filter_map(std::char::from_u32).collect()),
+        None => Ok(vec![chars[0]])
+    }
+}
+
+/// Input buffer size above which a warning is printed since each corpus file read in parallel has its own buffer.
+pub const INPUT_BUFFER_WARN: usize = 256 * 1024 * 1024;
+/// Largest input buffer size. Larger size is clamped to it.
+pub const INPUT_BUFFER_MAX: usize = 1024 * 1024 * 1024;
+
+/// Parse human readable buffer size, e.g. `8M`, into number of bytes.
+/// 
+/// # Return
+/// Err if `size` is not a valid size or it is 0.
+pub fn parse_buffer_size(size: &str) -> Result<usize, String> {
+    match unbytify(size) {
+        Ok(0) => Err("Input buffer size must be greater than 0".to_string()),
+        Ok(bytes) => Ok(bytes.min(usize::MAX as u64) as usize),
+        Err(_) => Err(format!("Invalid input buffer size \"{}\". Expect number of bytes with optional unit, e.g. 8M", size))
+    }
+}
+
+/// UTF-8 byte-order mark that some editors put at the start of text file.
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Consume UTF-8 byte-order mark at the start of `reader` if there is one.
+fn skip_bom<R: BufRead>(mut reader: R) -> R {
+    let has_bom = reader.fill_buf().map(|buf| buf.starts_with(UTF8_BOM)).unwrap_or(false);
+    if has_bom {
+        reader.consume(UTF8_BOM.len());
+    }
+
+    reader
+}
+
+/// A reader that transcode bytes of `inner` into UTF-8 as they are read.
+/// Malformed byte sequence is decoded to `\u{FFFD}`.
+struct Transcoder<R> {
+    inner: R,
+    decoder: encoding_rs::Decoder,
+    /// Transcoded bytes that is not read yet start at `pos`.
+    out: Vec<u8>,
+    pos: usize,
+    finished: bool
+}
+
+impl<R: BufRead> Read for Transcoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // decoder may hold incomplete byte sequence without producing any byte so keep feeding it
+        while self.pos == self.out.len() && !self.finished {
+            let input = self.inner.fill_buf()?;
+            let last = input.is_empty();
+            let max_len = self.decoder.max_utf8_buffer_length(input.len()).ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, "Input buffer is too large to be transcoded")
+            })?;
+            self.out.resize(max_len, 0);
+            let (_, read, written, _) = self.decoder.decode_to_utf8(input, &mut self.out, last);
+            self.out.truncate(written);
+            self.pos = 0;
+            self.finished = last;
+            self.inner.consume(read);
+        }
+        let len = buf.len().min(self.out.len() - self.pos);
+        buf[..len].copy_from_slice(&self.out[self.pos..(self.pos + len)]);
+        self.pos += len;
+
+        Ok(len)
+    }
+}
+
+/// Wrap `reader` of corpus in `encoding` so it yield UTF-8. Leading byte-order mark is skipped.
+fn decode_corpus<R: BufRead + 'static>(reader: R, encoding: Encoding) -> Box<dyn Read> {
+    match encoding.transcode_from() {
+        // decoder sniff byte-order mark by itself
+        Some(from) => Box::new(Transcoder {inner: reader, decoder: from.new_decoder(), out: vec![], pos: 0, finished: false}),
+        None => Box::new(skip_bom(reader))
+    }
+}
+
+/// Open a corpus source in `encoding` for reading with `buf_size` buffer of raw bytes.
+/// Path `-` means standard input. Corpus is transcoded to UTF-8 as it is read and
+/// leading byte-order mark is skipped.
+/// 
+/// # Return
+/// Err if `path` cannot be opened, e.g. it doesn't exist or it is not readable.
+pub fn open_corpus(path: &Path, buf_size: usize, encoding: Encoding) -> std::io::Result<Box<dyn Read>> {
+    if path == Path::new("-") {
+        Ok(decode_corpus(BufReader::with_capacity(buf_size, std::io::stdin()), encoding))
+    } else {
+        Ok(decode_corpus(BufReader::with_capacity(buf_size, File::open(path)?), encoding))
+    }
+}
+
+/// Open corpus source `path` by [open_corpus](fn.open_corpus.html) then pass each of its document to `consume`.
+/// See [for_each_corpus_document](fn.for_each_corpus_document.html).
+/// 
+/// # Return
+/// Err if `path` cannot be opened or parsed, or `consume` return Err.
+pub fn read_corpus<F>(read: ReadOption, path: &Path, consume: F) -> Result<(), String> where F: FnMut(Document) -> Result<(), String> {
+    let reader = open_corpus(path, read.buf_size, read.encoding).map_err(|err| format!("Fail to open corpus: {}", err))?;
+    for_each_corpus_document(reader, read.format, consume)
+}
+
+/// Fold case of `ch` by its lowercase if it is a single character. It is Unicode simple
+/// case folding for most characters. Caseless character, e.g. Thai, is returned as is.
+pub fn fold_case(ch: char) -> char {
+    let mut lower = ch.to_lowercase();
+    match (lower.next(), lower.next()) {
+        (Some(folded), None) => folded,
+        _ => ch
+    }
+}
+
+/// Vectorize a single char. If `ch` is a member of `equiv_classes` of `option`, it is vectorized
+/// as the representative of its class so every member share vectorized char.
+/// If `ch` is neither in `unicode_ranges` nor `char_include_list`
+/// of `option`, it return `None`. Otherwise, its case is folded first if `fold_case` of `option` is true.
+/// See [vectorize](fn.vectorize.html) for detail of each parameter.
+/// 
+/// # Return
+/// Err if `ch` need a new vectorized char but `id_width` of `option` is already exhausted.
+fn vectorize_char(ch: char, option: &VectorizeOption, init: &RwLock<Id>, map: &RwLock<HashMap<char, Id>>) -> Result<Option<Id>, String> {
+    let ch = if let Some(representative) = option.equiv_classes.representative(ch) {
+        representative
+    } else {
+        let codepoint = ch as u32;
+        if !option.unicode_ranges.iter().any(|r| r.contains(&codepoint)) && !option.char_include_list.contains(&ch) {
+            return Ok(None)
+        }
+        if option.fold_case { fold_case(ch) } else { ch }
+    };
+
+    {
+        let map = map.read().unwrap();
+
+        if let Some(v) = map.get(&ch) {
+            return Ok(Some(*v))
+        }
+    }
+    let mut map = map.write().unwrap();
+    // other thread may have inserted `ch` between releasing read lock and acquiring write lock
+    if let Some(v) = map.get(&ch) {
+        return Ok(Some(*v))
+    }
+    let mut v = init.write().unwrap();
+    // The largest id is reserved for oov token
+    if *v >= option.id_width.max() {
+        return Err(format!("Found {} distinct characters which exceed {} id width that can hold only {} characters", *v, option.id_width, option.id_width.max() - 1))
+    }
+    map.insert(ch, *v);
+
+    *v += 1;
+
+    Ok(Some(*v - 1))
+}
+
+/// Vectorize a single document and append the result to `tagged`, `sentence_lens`, and `document_lens` of `out`.
+/// See [vectorize](fn.vectorize.html) for detail of each parameter.
+fn vectorize_document(doc: &Document, option: &VectorizeOption, init: &RwLock<Id>, map: &RwLock<HashMap<char, Id>>, out: &mut Vectorized) -> Result<(), String> {
+    let doc_start = out.tagged.len();
+    for sentence in doc {
+        let mut tagged_sentence: Vec<(Id, Tag)> = vec![];
+
+        for (word, tag) in sentence {
+            if *tag > option.tag_width.max() {
+                return Err(format!("Tag {} exceed {} tag width. Use wider --tag-width", tag, option.tag_width))
+            }
+            let tag = option.tag_map.get(tag).unwrap_or(tag);
+            // excluded word is removed entirely so n-gram is formed as if it never exist
+            if !option.tag_filter.accept(*tag) {
+                continue
+            }
+            let chars: Box<dyn Iterator<Item = char>> = match option.normalization {
+                Normalization::None => Box::new(word.chars()),
+                Normalization::Nfc => Box::new(word.chars().nfc()),
+                Normalization::Nfd => Box::new(word.chars().nfd())
+            };
+            let vectorized = chars.map(|ch| vectorize_char(ch, option, init, map)).collect::<Result<Vec<Option<Id>>, String>>()?;
+            let mut tagged_chars: Vec<(Id, Tag)> = vectorized.into_iter().filter_map(|v| {
+                match v {
+                    Some(v) => Some((v, 0)),
+                    None => match option.oov_mode {
+                        OovMode::Drop => None,
+                        OovMode::Token => Some((option.id_width.max(), 0)),
+                        OovMode::KeepPosition => Some((0, 0))
+                    }
+                }
+            }).collect();
+            if let Some((_, ref mut l)) = tagged_chars.last_mut() {
+                *l = *tag;   
+                *out.word_lens.entry(tagged_chars.len()).or_insert(0) += 1;
+            }
+            tagged_sentence.append(&mut tagged_chars);
+        }
+
+        out.sentence_lens.push(tagged_sentence.len());
+        out.tagged.append(&mut tagged_sentence);
+    }
+    out.document_lens.push(out.tagged.len() - doc_start);
+
+    Ok(())
+}
+
+/// Check that `doc` has at least one sentence, each sentence has at least one word,
+/// and each word has at least one character.
+/// 
+/// # Return
+/// Description of every violation found in `doc`. Each description is prefixed by
+/// `doc_idx` and index of sentence and word that violate the rule.
+fn validate_document(doc_idx: usize, doc: &Document) -> Vec<String> {
+    if doc.is_empty() {
+        return vec![format!("document {} has no sentence", doc_idx)]
+    }
+
+    doc.iter().enumerate().flat_map(|(sentence_idx, sentence)| {
+        if sentence.is_empty() {
+            return vec![format!("document {} sentence {} has no word", doc_idx, sentence_idx)]
+        }
+
+        sentence.iter().enumerate().filter_map(|(word_idx, (word, _))| {
+            if word.is_empty() {
+                Some(format!("document {} sentence {} word {} has no character", doc_idx, sentence_idx, word_idx))
+            } else {
+                None
+            }
+        }).collect()
+    }).collect()
+}
+
+/// Remove corpus file that appear more than once in `corpuses`, keeping the first one.
+/// Paths are compared after being canonicalized so relative path and symlink that point
+/// to the same file are considered duplicate. A warning is printed for each duplicate dropped.
+fn dedup_corpuses(corpuses: Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut seen = HashSet::new();
+
+    corpuses.into_iter().filter(|path| {
+        let canonical = if path == Path::new("-") {
+            path.to_owned()
+        } else {
+            path.canonicalize().unwrap_or_else(|_| path.to_owned())
+        };
+        let first = seen.insert(canonical);
+        if !first {
+            warn!("{} is matched more than once. It'll be processed only once.", path.display());
+        }
+        first
+    }).collect()
+}
+
+/// Glob all the path `patterns` specified by user into deduplicated corpus files.
+/// Matched path that is not a regular file, e.g. directory, is skipped.
+/// Matched path that cannot be read is also skipped with a warning.
+/// A warning is printed for each pattern that match no file.
+/// 
+/// # Return
+/// Err if a pattern is malformed or no file matched any pattern.
+pub fn expand_corpuses(patterns: &[&str]) -> Result<Vec<PathBuf>, String> {
+    let mut corpuses = vec![];
+    for pattern in patterns {
+        let matched: Vec<PathBuf> = if *pattern == "-" {
+            // stdin cannot be globbed
+            vec![PathBuf::from(pattern)]
+        } else {
+            let paths = glob(pattern).map_err(|err| format!("Invalid corpus file pattern {}: {}", pattern, err))?;
+            // symlink is followed so link to a file is kept
+            paths.filter_map(|g| {
+                g.map_err(|err| warn!("Skip {} since it cannot be read: {}", err.path().display(), err.error())).ok()
+            }).filter(|path| {
+                let is_file = path.is_file();
+                if !is_file {
+                    debug!("Skip {} since it is not a file", path.display());
+                }
+                is_file
+            }).collect()
+        };
+        if matched.is_empty() {
+            warn!("No corpus file matched {}", pattern);
+        }
+        corpuses.extend(matched);
+    }
+    let corpuses = dedup_corpuses(corpuses);
+    if corpuses.is_empty() {
+        return Err(format!("No corpus files matched: {}", patterns.join(" ")))
+    }
+
+    Ok(corpuses)
+}
+
+/// Parse and validate all `corpuses` without vectorizing them.
+/// See [validate_document](fn.validate_document.html) for rule of validation.
+/// 
+/// # Return
+/// Description of every violation found, prefixed with path of corpus file.
+pub fn validate_corpuses(read: ReadOption, corpuses: &[PathBuf]) -> Vec<String> {
+    corpuses.par_iter().flat_map(|f| {
+        let mut violations = vec![];
+        let mut doc_idx = 0;
+        let result = read_corpus(read, f, |doc| {
+            violations.extend(validate_document(doc_idx, &doc).into_iter().map(|v| format!("{}: {}", f.display(), v)));
+            doc_idx += 1;
+            Ok(())
+        });
+        if let Err(err) = result {
+            violations.push(format!("{}: {}", f.display(), err));
+        }
+
+        violations
+    }).collect()
+}
+
+/// Count documents of each of `corpuses` without vectorizing them.
+/// If a file cannot be parsed, only documents before the error are counted.
+pub fn count_documents(read: ReadOption, corpuses: &[PathBuf]) -> Vec<usize> {
+    corpuses.par_iter().map(|f| {
+        let mut documents = 0;
+        // error is reported later when the file is vectorized
+        let _ = read_corpus(read, f, |_| {
+            documents += 1;
+            Ok(())
+        });
+
+        documents
+    }).collect()
+}
+
+/// Parse only the first document of each of `corpuses` to quickly check that they are readable.
+/// 
+/// # Return
+/// Description of every file that cannot be parsed, prefixed with path of corpus file.
+pub fn peek_corpuses(read: ReadOption, corpuses: &[PathBuf]) -> Vec<String> {
+    corpuses.par_iter().filter_map(|f| {
+        let mut found = false;
+        // stop right after first document by failing consume
+        let result = read_corpus(read, f, |_| {
+            found = true;
+            Err(String::new())
+        });
+        match result {
+            Err(err) if !found => Some(format!("{}: {}", f.display(), err)),
+            _ => None
+        }
+    }).collect()
+}
+
+/// Vectorize a single corpus file `f`. See [vectorize](fn.vectorize.html) for detail of each parameter.
+/// 
+/// # Return
+/// Err if `id_width` of `option` is exhausted. If `f` cannot be opened or parsed, it is Err when `strict`
+/// of `read` is true. Otherwise, a warning is printed and it return None.
+fn vectorize_file(read: ReadOption, option: &VectorizeOption, f: &Path, init: &RwLock<Id>, map: &RwLock<HashMap<char, Id>>) -> Result<Option<Vectorized>, String> {
+    debug!("Parsing:{}", f.display());
+    let timer = Instant::now();
+    let mut vectorized = Vectorized::default();
+    let mut documents = 0;
+    let mut words = 0;
+    let mut vectorize_failed = false;
+    let mut vectorize_time = Duration::default();
+    let mut doc_idx = 0;
+    let result = read_corpus(read, f, |doc| {
+        doc_idx += 1;
+        if !option.keep_document(f, doc_idx - 1) {
+            return Ok(())
+        }
+        documents += 1;
+        words += doc.iter().map(|sentence| sentence.len()).sum::<usize>();
+        let vectorize_timer = Instant::now();
+        let result = vectorize_document(&doc, option, init, map, &mut vectorized);
+        vectorized.document_indices.push(doc_idx - 1);
+        vectorize_time += vectorize_timer.elapsed();
+        vectorize_failed = result.is_err();
+        result
+    }).map_err(|err| format!("{}: {}", f.display(), err));
+    match result {
+        Err(err) if vectorize_failed || read.strict => return Err(err),
+        Err(err) => {
+            warn!("Skip {}", err);
+            return Ok(None)
+        },
+        Ok(()) => ()
+    }
+    vectorized.file_stats.push(FileStats {
+        path: f.to_owned(),
+        documents,
+        sentences: vectorized.sentence_lens.len(),
+        words,
+        chars: vectorized.tagged.len(),
+        parse_time: timer.elapsed(),
+        vectorize_time
+    });
+
+    Ok(Some(vectorized))
+}
+
+/// Progress report and stop request of [vectorize](fn.vectorize.html).
+#[derive(Clone, Copy)]
+pub struct VectorizeControl<'a> {
+    /// A progress bar that is increased by 1 when a corpus file is completely vectorized.
+    pub progress: &'a ProgressBar,
+    /// Set once the run is requested to stop. Work already started is completed
+    /// but corpus file that is not started yet is not vectorized.
+    pub interrupted: &'a AtomicBool
+}
+
+/// Vectorize all `corpuses` in given paths using pre-defined `map`.
+/// If char in corpus is not exist in the map, it'll use `init` as 
+/// vectorized value then assign new `char` and `init` into map.
+/// It then increase init by 1.
+/// If corpus contain characters outside of `unicode_ranges` and it need to be vectorized
+/// then it has to be in char_include_list. Otherwise, it is handled by `oov_mode`.
+/// Each corpus file is streamed. Each document is vectorized and discarded
+/// as soon as it is read. Once `interrupted` of `control` is set, corpus file that
+/// is not started yet is not vectorized.
+/// 
+/// # Parameter
+/// - `read` - Options that control how corpus files are read. See [ReadOption](struct.ReadOption.html).
+/// - `option` - Options that control which character get vectorized. See [VectorizeOption](struct.VectorizeOption.html).
+/// - `corpuses` - Slice of PathBuf that point to corpus file. `-` means standard input.
+/// - `init` - An RwLock that store Id. An unsign int value that will be used
+///   on char that has no map inside `map` table yet.
+/// - `map` - A HashMap that map a character to unsign int.
+/// - `control` - Progress bar and stop request of the run. See [VectorizeControl](struct.VectorizeControl.html).
+/// - `checkpoint` - Optional checkpoint. File already in checkpoint is not read again and
+///   each newly vectorized file is recorded into it.
+/// 
+/// # Return
+/// Err if any corpus cannot be opened or parsed, there are more distinct characters than `id_width`
+/// of `option` can hold, or any tag is larger than `tag_width` of `option` allow.
+/// Otherwise, vectorized corpus. It contains a Vec of pair of Id and Tag. The Id is vectorized char. 
+/// The Tag is tag. It also contains length of each sentence in the same order
+/// as it is laid out in the Vec of pair and statistic of each corpus file.
+pub fn vectorize(read: ReadOption, option: &VectorizeOption, corpuses: &[PathBuf], init: &RwLock<Id>, map: &RwLock<HashMap<char, Id>>, control: VectorizeControl, checkpoint: Option<&Checkpoint>) -> Result<Vectorized, String> {
+    let files: Vec<Option<Vectorized>> = corpuses.par_iter().map(|f| -> Result<Option<Vectorized>, String> {
+        if checkpoint.map(|c| c.contains(f)).unwrap_or(false) {
+            debug!("Resume:{}", f.display());
+            control.progress.inc(1);
+            return Ok(None)
+        }
+        if control.interrupted.load(Ordering::SeqCst) {
+            return Ok(None)
+        }
+        let vectorized = vectorize_file(read, option, f, init, map)?;
+        control.progress.inc(1);
+
+        match (checkpoint, vectorized) {
+            (_, None) => Ok(None),
+            (Some(checkpoint), Some(vectorized)) => {
+                checkpoint.record(f, vectorized, init, map)?;
+                Ok(None)
+            },
+            (None, vectorized) => Ok(vectorized)
+        }
+    }).collect::<Result<Vec<Option<Vectorized>>, String>>()?;
+
+    // vectorized file held by checkpoint is taken back in corpus order
+    // and reported by the path given in this run rather than its canonical path
+    let files = files.into_iter().zip(corpuses).map(|(file, f)| {
+        file.or_else(|| checkpoint.and_then(|c| c.take(f)).map(|mut file| {
+            file.file_stats.iter_mut().for_each(|stat| stat.path = f.to_owned());
+            file
+        })).unwrap_or_default()
+    });
+    Ok(files.fold(Vectorized::default(), |mut all, mut file| {
+        all.tagged.append(&mut file.tagged);
+        all.sentence_lens.append(&mut file.sentence_lens);
+        all.document_lens.append(&mut file.document_lens);
+        all.document_indices.append(&mut file.document_indices);
+        all.file_stats.append(&mut file.file_stats);
+        for (len, words) in file.word_lens {
+            *all.word_lens.entry(len).or_insert(0) += words;
+        }
+        all
+    }))
+}
+
+/// Parse corpus file `path` on a background thread and yield each document as soon as it is parsed.
+/// The thread wait until previous document is taken so only one document is held at a time.
+/// If the iterator is dropped, the thread stop at next document.
+fn document_iter(read: ReadOption, path: PathBuf) -> impl Iterator<Item = Result<Document, String>> {
+    let (sender, receiver) = std::sync::mpsc::sync_channel(1);
+    std::thread::spawn(move || {
+        let result = read_corpus(read, &path, |doc| {
+            sender.send(Ok(doc)).map_err(|_| "Document is no longer consumed".to_string())
+        });
+        if let Err(err) = result {
+            // receiver may be already dropped, there is no one to report to
+            let _ = sender.send(Err(format!("{}: {}", path.display(), err)));
+        }
+    });
+
+    receiver.into_iter()
+}
+
+/// Lazily vectorize all `corpuses` and yield each pair of vectorized char and tag in
+/// the same order as `tagged` returned by [vectorize](fn.vectorize.html).
+/// Files are read one after another and only a single document is held in memory at a time.
+/// Characters are vectorized with the same `init` and `map` as `vectorize` so both can share a char map.
+/// See [vectorize](fn.vectorize.html) for detail of each parameter.
+/// 
+/// # Return
+/// An iterator of pair of vectorized char and tag. If any corpus cannot be parsed or there are
+/// more distinct characters than `id_width` of `option` can hold, it yield an Err then stop.
+pub fn tagged_char_iter<'a>(read: ReadOption, option: &'a VectorizeOption, corpuses: &'a [PathBuf], init: &'a RwLock<Id>, map: &'a RwLock<HashMap<char, Id>>) -> impl Iterator<Item = Result<(Id, Tag), String>> + 'a {
+    corpuses.iter().flat_map(move |f| {
+        document_iter(read, f.to_owned()).enumerate().filter(move |(doc_idx, doc)| {
+            doc.is_err() || option.keep_document(f, *doc_idx)
+        }).map(|(_, doc)| doc)
+    }).flat_map(move |doc| -> Box<dyn Iterator<Item = Result<(Id, Tag), String>>> {
+        let mut vectorized = Vectorized::default();
+        match doc.and_then(|doc| vectorize_document(&doc, option, init, map, &mut vectorized)) {
+            Ok(()) => Box::new(vectorized.tagged.into_iter().map(Ok)),
+            Err(err) => Box::new(std::iter::once(Err(err)))
+        }
+    }).scan(false, |failed, item| {
+        if *failed {
+            return None
+        }
+        *failed = item.is_err();
+        Some(item)
+    })
+}
+
+/// Version of checkpoint file format.
+pub const CHECKPOINT_VERSION: u64 = 5;
+
+/// Key of corpus file `path` in checkpoint. It is canonicalized so the same file is
+/// recognized no matter which directory the run is started from.
+fn checkpoint_key(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_owned())
+}
+
+/// Progress of vectorization persisted in a file so an interrupted run can resume.
+/// 
+/// The file start with a header line `BSTCHECKPOINT <version>` followed by a JSON object
+/// per line, one for each fully vectorized corpus file. Lines are only appended so each
+/// corpus file is written once. Each object has canonical `path` of the file, `next_id` which
+/// is the next unassigned vectorized char when it is recorded, `map` which is an array of char
+/// and its vectorized char pair assigned since previous line, `ids` and `tags` of vectorized
+/// chars, `sentence_lens`, `document_lens`, `document_indices`, `word_lens`, and `stats` of the file.
+/// Each line is flushed to disk before the next one is written. A partially written last line,
+/// e.g. from a crash, is discarded when the checkpoint is loaded.
+pub struct Checkpoint {
+    path: PathBuf,
+    files: Mutex<HashMap<PathBuf, Vectorized>>,
+    /// Next unassigned vectorized char when the last line is recorded.
+    recorded_id: Mutex<Id>
+}
+
+impl Checkpoint {
+    /// Load checkpoint from `path` and restore `init` and `map` from it.
+    /// If `path` doesn't exist, it is an empty checkpoint.
+    /// 
+    /// # Return
+    /// Err if `path` exist but it is not a valid checkpoint of supported version.
+    pub fn load(path: &Path, init: &RwLock<Id>, map: &RwLock<HashMap<char, Id>>) -> Result<Checkpoint, String> {
+        let mut checkpoint = Checkpoint {
+            path: path.to_owned(),
+            files: Mutex::new(HashMap::new()),
+            recorded_id: Mutex::new(*init.read().unwrap())
+        };
+        if !path.exists() {
+            return Ok(checkpoint)
+        }
+        let invalid = |line: usize, field: &str| format!("Invalid checkpoint {}: missing or malformed {} at line {}", path.display(), field, line);
+        let read_error = |err: std::io::Error| format!("Fail to read checkpoint {}: {}", path.display(), err);
+        let file = File::open(path).map_err(|err| format!("Fail to open checkpoint {}: {}", path.display(), err))?;
+        let mut reader = BufReader::new(file);
+        let mut header = String::new();
+        let mut valid_len = reader.read_line(&mut header).map_err(read_error)?;
+        match header.trim_end().strip_prefix("BSTCHECKPOINT ").and_then(|v| v.parse::<u64>().ok()) {
+            Some(CHECKPOINT_VERSION) => (),
+            Some(version) => return Err(format!("Checkpoint {} has version {} but only version {} is supported", path.display(), version, CHECKPOINT_VERSION)),
+            None => return Err(invalid(1, "header"))
+        }
+        let mut next_id = *init.read().unwrap();
+        let mut restored_map = HashMap::new();
+        let files = checkpoint.files.get_mut().unwrap();
+        let mut line = vec![];
+        for line_no in 2.. {
+            line.clear();
+            let len = reader.read_until(b'\n', &mut line).map_err(read_error)?;
+            if len == 0 {
+                break
+            }
+            let file: serde_json::Value = match serde_json::from_slice(&line) {
+                Ok(file) => file,
+                // crash while appending leave a line without line break which is the last one
+                Err(_) if line.last() != Some(&b'\n') => {
+                    warn!("Discard partially written line {} of checkpoint {}", line_no, path.display());
+                    std::fs::OpenOptions::new().write(true).open(path).and_then(|f| f.set_len(valid_len as u64)).map_err(read_error)?;
+                    break
+                },
+                Err(err) => return Err(format!("Invalid checkpoint {} at line {}: {}", path.display(), line_no, err))
+            };
+            valid_len += len;
+            let invalid = |field: &str| invalid(line_no, field);
+            let path = PathBuf::from(file["path"].as_str().ok_or_else(|| invalid("path"))?);
+            next_id = file["next_id"].as_u64().ok_or_else(|| invalid("next_id"))? as Id;
+            for pair in file["map"].as_array().ok_or_else(|| invalid("map"))? {
+                let ch = pair[0].as_str().and_then(|c| c.chars().next()).ok_or_else(|| invalid("map"))?;
+                let id = pair[1].as_u64().ok_or_else(|| invalid("map"))?;
+                restored_map.insert(ch, id as Id);
+            }
+            let numbers = |name: &str| file[name].as_array().and_then(|a| a.iter().map(|n| n.as_u64()).collect::<Option<Vec<u64>>>()).ok_or_else(|| invalid(name));
+            let usizes = |name: &str| numbers(name).map(|a| a.into_iter().map(|n| n as usize).collect::<Vec<usize>>());
+            let ids = numbers("ids")?;
+            let tags = numbers("tags")?;
+            if ids.len() != tags.len() {
+                return Err(invalid("tags"))
+            }
+            let tagged = ids.into_iter().zip(tags).map(|(id, tag)| (id as Id, tag as Tag)).collect();
+            let sentence_lens = usizes("sentence_lens")?;
+            let document_lens = usizes("document_lens")?;
+            let document_indices = usizes("document_indices")?;
+            let word_lens = file["word_lens"].as_array().ok_or_else(|| invalid("word_lens"))?.iter().map(|w| {
+                Some((w[0].as_u64()? as usize, w[1].as_u64()? as usize))
+            }).collect::<Option<HashMap<usize, usize>>>().ok_or_else(|| invalid("word_lens"))?;
+            let stats = &file["stats"];
+            let stat = |name: &str| stats[name].as_u64().map(|n| n as usize).ok_or_else(|| invalid(name));
+            let file_stats = vec![FileStats {
+                path: path.clone(),
+                documents: stat("documents")?,
+                sentences: stat("sentences")?,
+                words: stat("words")?,
+                chars: stat("chars")?,
+                parse_time: Duration::from_millis(stat("parse_ms")? as u64),
+                vectorize_time: Duration::from_millis(stat("vectorize_ms")? as u64)
+            }];
+            files.insert(path, Vectorized {tagged, sentence_lens, document_lens, document_indices, file_stats, word_lens});
+        }
+
+        *checkpoint.recorded_id.get_mut().unwrap() = next_id;
+        *init.write().unwrap() = next_id;
+        *map.write().unwrap() = restored_map;
+        Ok(checkpoint)
+    }
+
+    /// Number of fully vectorized files in checkpoint.
+    pub fn len(&self) -> usize {
+        self.files.lock().unwrap().len()
+    }
+
+    /// Return true if no file is fully vectorized yet.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return true if `path` is already fully vectorized.
+    fn contains(&self, path: &Path) -> bool {
+        self.files.lock().unwrap().contains_key(&checkpoint_key(path))
+    }
+
+    /// Remove vectorized file `path` out of checkpoint.
+    fn take(&self, path: &Path) -> Option<Vectorized> {
+        self.files.lock().unwrap().remove(&checkpoint_key(path))
+    }
+
+    /// Append fully `vectorized` file `path` along with chars of `map` assigned since previous record,
+    /// and next unassigned vectorized char `init` to checkpoint.
+    fn record(&self, path: &Path, vectorized: Vectorized, init: &RwLock<Id>, map: &RwLock<HashMap<char, Id>>) -> Result<(), String> {
+        // hold files lock while writing so checkpoint is written by one thread at a time
+        let mut files = self.files.lock().unwrap();
+        let mut recorded_id = self.recorded_id.lock().unwrap();
+        let key = checkpoint_key(path);
+        let (map, next_id) = {
+            // no char can be assigned while map is read so next_id cover every char of map
+            let map = map.read().unwrap();
+            let next_id = *init.read().unwrap();
+            let mut assigned: Vec<(String, Id)> = map.iter().filter(|(_, v)| **v >= *recorded_id).map(|(c, v)| (c.to_string(), *v)).collect();
+            assigned.sort_unstable_by_key(|(_, v)| *v);
+            (assigned, next_id)
+        };
+        let stats = &vectorized.file_stats[0];
+        let json = serde_json::json!({
+            "path": key.to_string_lossy(),
+            "next_id": next_id,
+            "map": map,
+            "ids": vectorized.tagged.iter().map(|(v, _)| *v).collect::<Vec<Id>>(),
+            "tags": vectorized.tagged.iter().map(|(_, t)| *t).collect::<Vec<Tag>>(),
+            "sentence_lens": vectorized.sentence_lens,
+            "document_lens": vectorized.document_lens,
+            "document_indices": vectorized.document_indices,
+            "word_lens": vectorized.word_lens.iter().collect::<Vec<(&usize, &usize)>>(),
+            "stats": {
+                "documents": stats.documents,
+                "sentences": stats.sentences,
+                "words": stats.words,
+                "chars": stats.chars,
+                "parse_ms": stats.parse_time.as_millis() as u64,
+                "vectorize_ms": stats.vectorize_time.as_millis() as u64
+            }
+        });
+        let write = || -> std::io::Result<()> {
+            let file = std::fs::OpenOptions::new().create(true).append(true).open(&self.path)?;
+            let mut writer = BufWriter::new(file);
+            if writer.get_ref().metadata()?.len() == 0 {
+                writeln!(writer, "BSTCHECKPOINT {}", CHECKPOINT_VERSION)?;
+            }
+            serde_json::to_writer(&mut writer, &json)?;
+            writeln!(writer)?;
+            writer.flush()?;
+            writer.get_ref().sync_data()
+        };
+
+        write().map_err(|err| format!("Fail to write checkpoint {}: {}", self.path.display(), err))?;
+        files.insert(key, vectorized);
+        *recorded_id = next_id;
+        Ok(())
+    }
+}
+
+/// Error of writing `what` into `path` that tell which file cannot be written.
+fn write_error(what: &str, path: &Path, err: impl fmt::Display) -> std::io::Error {
+    std::io::Error::other(format!("Fail to write {} to {}: {}", what, path.display(), err))
+}
+
+/// Write file `path` by `write` into a temporary file next to it then rename it to `path`
+/// so `path` is never partially written. The temporary file is removed if anything fail.
+pub fn write_atomically<F>(path: &Path, write: F) -> std::io::Result<()> where F: FnOnce(&File) -> std::io::Result<()> {
+    let temp = suffixed_path(path, "tmp");
+    let file = File::create(&temp)?;
+    let written = write(&file).and_then(|_| file.sync_all());
+    drop(file);
+    let result = written.and_then(|_| std::fs::rename(&temp, path));
+    // partially written temporary file is never renamed so it is only left over
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+
+    result
+}
+
+/// Options that control where n-gram windows start.
+pub struct WindowOption<'a> {
+    /// Distance between start of two consecutive windows. 1 make windows fully overlap
+    /// while number of gram make windows tile without overlapping. It must be at least 1.
+    pub stride: usize,
+    /// Length of each segment, i.e. sentence or document, in vectorized corpus. If it is `None`,
+    /// the corpus is treated as a single continuous sequence. Otherwise, only windows that
+    /// fully lie inside a single segment are used and `stride` restart at each segment.
+    pub sentence_lens: Option<&'a [usize]>
+}
+
+/// Get start index of every n-gram window in `raw` of length `len`.
+/// 
+/// # Parameter
+/// - `gram` - Number of gram.
+/// - `len` - Total length of vectorized corpus.
+/// - `window` - Options that control where windows start. See [WindowOption](struct.WindowOption.html).
+pub fn window_starts(gram: usize, len: usize, window: &WindowOption) -> Vec<usize> {
+    match window.sentence_lens {
+        Some(lens) => {
+            let mut offset = 0;
+            lens.iter().flat_map(|l| {
+                let start = offset;
+                offset += l;
+                if *l >= gram {
+                    (start..(start + l - gram + 1)).step_by(window.stride)
+                } else {
+                    (0..0).step_by(1)
+                }
+            }).collect()
+        },
+        None if len >= gram => {
+            (0..(len - gram + 1)).step_by(window.stride).collect()
+        },
+        None => vec![]
+    }
+}
+
+/// Frequency table of n-gram. Each element is n-gram and its number of occurrence.
+pub type NgramCounts<T> = Vec<(Vec<T>, usize)>;
+
+/// Frequency table of n-gram along with total of every n-gram counted.
+pub struct CountedNgrams<T> {
+    /// N-gram and its number of occurrence, sorted by n-gram. If number of kept n-gram is
+    /// capped, only the most frequent n-grams are kept.
+    pub counts: NgramCounts<T>,
+    /// Number of unique n-gram, including those that are not kept.
+    pub unique: usize,
+    /// Number of n-gram windows, including those of n-gram that are not kept.
+    pub windows: usize,
+    /// Number of documents that each n-gram of `counts` occur in, in the same order as `counts`.
+    /// See [add_document_frequency](fn.add_document_frequency.html).
+    pub document_frequency: Option<Vec<usize>>,
+    /// Number of n-gram windows that contain excluded character, including those of n-gram
+    /// that are not kept. See [add_oov_windows](fn.add_oov_windows.html).
+    pub oov_windows: Option<usize>
+}
+
+/// Collect `groups`, the n-gram and its number of occurrence sorted by n-gram, into frequency table.
+/// If `max_ngrams` is given, only that many most frequent n-grams are kept using a bounded
+/// min-heap so n-gram that is not kept is never copied. On tie, the smaller n-gram is kept.
+fn collect_groups<'a, T, I>(groups: I, max_ngrams: Option<usize>) -> CountedNgrams<T>
+where T: Token + 'a, I: Iterator<Item = (&'a [T], usize)> {
+    let mut unique = 0;
+    let mut windows = 0;
+    let counts = match max_ngrams {
+        Some(max) => {
+            let mut heap = BinaryHeap::with_capacity(max + 1);
+            for (ngram, count) in groups {
+                unique += 1;
+                windows += count;
+                heap.push(Reverse((count, Reverse(ngram))));
+                if heap.len() > max {
+                    heap.pop();
+                }
+            }
+            let mut counts: NgramCounts<T> = heap.into_iter().map(|Reverse((count, Reverse(ngram)))| (ngram.to_vec(), count)).collect();
+            counts.par_sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+            counts
+        },
+        None => groups.map(|(ngram, count)| {
+            unique += 1;
+            windows += count;
+            (ngram.to_vec(), count)
+        }).collect()
+    };
+
+    CountedNgrams {counts, unique, windows, document_frequency: None, oov_windows: None}
+}
+
+/// Element of n-gram window. It is either a vectorized char alone or a pair of
+/// vectorized char and tag, in which case tag participate in n-gram uniqueness.
+pub trait Token: Ord + Clone + Hash + Send + Sync {
+    /// Whether this token carry tag.
+    const TAGGED: bool;
+
+    /// Vectorized char of this token.
+    fn id(&self) -> Id;
+
+    /// Tag of this token. It is always 0 if token doesn't carry tag.
+    fn tag(&self) -> Tag;
+}
+
+impl Token for Id {
+    const TAGGED: bool = false;
+
+    fn id(&self) -> Id {
+        *self
+    }
+
+    fn tag(&self) -> Tag {
+        0
+    }
+}
+
+impl Token for (Id, Tag) {
+    const TAGGED: bool = true;
+
+    fn id(&self) -> Id {
+        self.0
+    }
+
+    fn tag(&self) -> Tag {
+        self.1
+    }
+}
+
+/// Sort `starts` of n-gram windows of `raw` by content of window they point to across rayon threads.
+/// Each window is compared as a slice of `raw` so no window is ever copied.
+/// Order of starts of identical windows is unspecified.
+pub fn sorted_windows<T: Token>(gram: u8, raw: &[T], mut starts: Vec<usize>) -> Vec<usize> {
+    let g = gram as usize;
+    starts.par_sort_unstable_by(|a, b| raw[*a..(*a + g)].cmp(&raw[*b..(*b + g)]));
+
+    starts
+}
+
+/// Get index of first window of each group of identical windows in
+/// `flatten`, the sorted start of n-gram windows of `raw`.
+/// Adjacent windows are compared across rayon threads.
+/// An empty `flatten` has no group. Otherwise, the first window always start a group.
+pub fn get_unique_vecs_idx<T: Token>(gram: u8, raw: &[T], flatten: &[usize]) -> Vec<usize> {
+    if flatten.is_empty() {
+        return vec![]
+    }
+    let g = gram as usize;
+    let mut unique_idx = vec![0];
+    unique_idx.par_extend(flatten.par_windows(2).enumerate().filter_map(|(i, pair)| {
+        if raw[pair[0]..(pair[0] + g)].eq(&raw[pair[1]..(pair[1] + g)]) {
+            None
+        } else {
+            Some(i + 1)
+        }
+    }));
+
+    unique_idx
+}
+
+/// Time spent on each stage of counting n-gram.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CountTimes {
+    /// Time spent generating start of every n-gram window.
+    pub flatten: Duration,
+    /// Time spent sorting n-gram windows.
+    pub sort: Duration,
+    /// Time spent grouping sorted windows into frequency table.
+    pub group: Duration
+}
+
+impl CountTimes {
+    /// Time spent on every stage.
+    pub fn total(&self) -> Duration {
+        self.flatten + self.sort + self.group
+    }
+}
+
+impl std::ops::Div<u32> for CountTimes {
+    type Output = CountTimes;
+
+    fn div(self, n: u32) -> CountTimes {
+        CountTimes {
+            flatten: self.flatten / n,
+            sort: self.sort / n,
+            group: self.group / n
+        }
+    }
+}
+
+/// Count frequency of each unique n-gram window of `raw` that start at given `starts`.
+/// Only `max_ngrams` most frequent n-grams are kept if it is given.
+/// Time spent sorting and grouping windows is added to `times`.
+fn count_windows<T: Token>(gram: u8, raw: &[T], starts: Vec<usize>, max_ngrams: Option<usize>, times: &mut CountTimes) -> CountedNgrams<T> {
+    let g = gram as usize;
+    let timer = Instant::now();
+    let flatten = sorted_windows(gram, raw, starts);
+    times.sort += timer.elapsed();
+    let timer = Instant::now();
+    let unique_idx = get_unique_vecs_idx(gram, raw, &flatten);
+    let ends = unique_idx.iter().skip(1).cloned().chain(std::iter::once(flatten.len()));
+
+    let counted = collect_groups(unique_idx.iter().zip(ends).map(|(start, end)| {
+        let i = flatten[*start];
+        (&raw[i..(i + g)], end - start)
+    }), max_ngrams);
+    times.group += timer.elapsed();
+
+    counted
+}
+
+/// Count frequency of each unique n-gram in `raw`.
+/// 
+/// # Parameter
+/// - `gram` - Number of gram.
+/// - `raw` - Vectorized corpus. Either vectorized chars alone or vectorized chars with tag.
+/// - `window` - Options that control where windows start. See [WindowOption](struct.WindowOption.html).
+/// 
+/// # Return
+/// Vec of n-gram and its number of occurrence, sorted by n-gram.
+pub fn count_ngrams<T: Token>(gram: u8, raw: &[T], window: &WindowOption) -> Vec<(Vec<T>, usize)> {
+    count_ngrams_timed(gram, raw, window, None, &mut CountTimes::default()).counts
+}
+
+/// Get every distinct n-gram in `raw`.
+///
+/// # Parameter
+/// - `gram` - Number of gram.
+/// - `raw` - Vectorized corpus. Either vectorized chars alone or vectorized chars with tag.
+/// - `window` - Options that control where windows start. See [WindowOption](struct.WindowOption.html).
+///
+/// # Return
+/// Vec of distinct n-gram, sorted by n-gram.
+pub fn unique_ngrams<T: Token>(gram: u8, raw: &[T], window: &WindowOption) -> Vec<Vec<T>> {
+    let g = gram as usize;
+    let flatten = sorted_windows(gram, raw, window_starts(g, raw.len(), window));
+
+    get_unique_vecs_idx(gram, raw, &flatten).into_iter().map(|i| {
+        let start = flatten[i];
+        raw[start..(start + g)].to_vec()
+    }).collect()
+}
+
+/// Count distinct n-gram in `raw` by inserting every window into a HashSet.
+/// It is much slower than [unique_ngrams](fn.unique_ngrams.html) but simple enough to
+/// cross-check the sort based dedup of [get_unique_vecs_idx](fn.get_unique_vecs_idx.html).
+pub fn brute_force_unique<T: Token>(gram: u8, raw: &[T], window: &WindowOption) -> usize {
+    let g = gram as usize;
+    let unique: HashSet<&[T]> = window_starts(g, raw.len(), window).into_iter().map(|start| &raw[start..(start + g)]).collect();
+
+    unique.len()
+}
+
+/// Same as [count_ngrams](fn.count_ngrams.html) but only `max_ngrams` most frequent n-grams
+/// are kept if it is given and time spent on each stage is added to `times`.
+pub fn count_ngrams_timed<T: Token>(gram: u8, raw: &[T], window: &WindowOption, max_ngrams: Option<usize>, times: &mut CountTimes) -> CountedNgrams<T> {
+    let timer = Instant::now();
+    let starts = window_starts(gram as usize, raw.len(), window);
+    times.flatten += timer.elapsed();
+
+    count_windows(gram, raw, starts, max_ngrams, times)
+}
+
+/// Count frequency of each unique n-gram in `raw` separately for n-gram that lie
+/// entirely inside a single word and n-gram that span across word boundary.
+/// A window span across word boundary if any character of it, except the last one,
+/// is the last character of a word. Since only the last character of word carry tag,
+/// a character is considered the last character of a word if its tag is not 0.
+/// 
+/// # Parameter
+/// - `gram` - Number of gram.
+/// - `raw` - Vectorized corpus. Either vectorized chars alone or vectorized chars with tag.
+/// - `labels` - Tag of each character in `raw`.
+/// - `window` - Options that control where windows start. See [WindowOption](struct.WindowOption.html).
+/// 
+/// # Return
+/// A tuple of intra-word n-gram frequency and inter-word n-gram frequency. 
+/// Both are sorted by n-gram.
+pub fn count_ngrams_by_word_boundary<T: Token>(gram: u8, raw: &[T], labels: &[Tag], window: &WindowOption) -> (NgramCounts<T>, NgramCounts<T>) {
+    let g = gram as usize;
+    let (inter, intra): (Vec<usize>, Vec<usize>) = window_starts(g, raw.len(), window).into_par_iter().partition(|i| {
+        labels[*i..(*i + g - 1)].iter().any(|l| *l != 0)
+    });
+
+    let mut times = CountTimes::default();
+    (count_windows(gram, raw, intra, None, &mut times).counts, count_windows(gram, raw, inter, None, &mut times).counts)
+}
+
+/// Start index of every n-gram window in `sentence_lens` bucketed by position of the window
+/// in its sentence. The first window of a sentence is at its start, the last window of a
+/// sentence is at its end and the others are in its middle. Window of a sentence that has
+/// only a single window is at both its start and its end.
+///
+/// # Parameter
+/// - `gram` - Number of gram.
+/// - `sentence_lens` - Length of each sentence in vectorized corpus.
+/// - `stride` - Distance between start of two consecutive windows of a sentence.
+///
+/// # Return
+/// A tuple of start index of windows at sentence start, middle, and end.
+pub fn positional_window_starts(gram: usize, sentence_lens: &[usize], stride: usize) -> (Vec<usize>, Vec<usize>, Vec<usize>) {
+    let (mut start, mut middle, mut end) = (vec![], vec![], vec![]);
+    let mut offset = 0;
+    for l in sentence_lens {
+        if *l >= gram {
+            let starts: Vec<usize> = (offset..(offset + l - gram + 1)).step_by(stride).collect();
+            start.push(starts[0]);
+            end.push(starts[starts.len() - 1]);
+            if starts.len() > 2 {
+                middle.extend_from_slice(&starts[1..(starts.len() - 1)]);
+            }
+        }
+        offset += l;
+    }
+
+    (start, middle, end)
+}
+
+/// Count frequency of each unique n-gram in `raw` separately for n-gram at start, middle,
+/// and end of sentence. See [positional_window_starts](fn.positional_window_starts.html)
+/// for the bucketing rule.
+///
+/// # Parameter
+/// - `gram` - Number of gram.
+/// - `raw` - Vectorized corpus. Either vectorized chars alone or vectorized chars with tag.
+/// - `sentence_lens` - Length of each sentence in `raw`.
+/// - `stride` - Distance between start of two consecutive windows of a sentence.
+///
+/// # Return
+/// A tuple of n-gram frequency at sentence start, middle, and end. Each is sorted by n-gram.
+pub fn count_ngrams_by_position<T: Token>(gram: u8, raw: &[T], sentence_lens: &[usize], stride: usize) -> (NgramCounts<T>, NgramCounts<T>, NgramCounts<T>) {
+    let (start, middle, end) = positional_window_starts(gram as usize, sentence_lens, stride);
+
+    let mut times = CountTimes::default();
+    (
+        count_windows(gram, raw, start, None, &mut times).counts,
+        count_windows(gram, raw, middle, None, &mut times).counts,
+        count_windows(gram, raw, end, None, &mut times).counts
+    )
+}
+
+/// Count frequency of each unique n-gram in `raw` for every gram in `grams` in a single pass.
+/// 
+/// Windows are generated once for the smallest gram. Each window is truncated to the
+/// largest gram or to the end of its sentence or corpus, whichever come first, then all
+/// windows are sorted once. Since a slice sort before every slice that it is a prefix of,
+/// the windows that are at least `g` long are also sorted by their first `g` elements.
+/// Counts of each gram `g` are grouped from adjacent windows of that sorted order with
+/// windows shorter than `g` skipped. The result is identical to calling
+/// [count_ngrams](fn.count_ngrams.html) on each gram.
+/// 
+/// With `W` windows and largest gram `G`, sorting take O(W log W) comparisons of at most `G`
+/// elements each and grouping gram `g` take O(W g). Counting each gram independently instead
+/// sort the windows once per gram.
+/// 
+/// # Parameter
+/// - `grams` - Number of gram of each frequency table.
+/// - `raw` - Vectorized corpus. Either vectorized chars alone or vectorized chars with tag.
+/// - `window` - Options that control where windows start. See [WindowOption](struct.WindowOption.html).
+/// - `max_ngrams` - If it is given, only that many most frequent n-grams of each gram are kept.
+/// - `times` - Time spent on each stage of the pass is added to it.
+/// 
+/// # Return
+/// Frequency table of each gram in the same order as `grams`. Each table is sorted by n-gram.
+pub fn count_ngrams_combined<T: Token>(grams: &[u8], raw: &[T], window: &WindowOption, max_ngrams: Option<usize>, times: &mut CountTimes) -> Vec<CountedNgrams<T>> {
+    let (min_gram, max_gram) = match (grams.iter().min(), grams.iter().max()) {
+        (Some(min), Some(max)) => (*min as usize, *max as usize),
+        _ => return vec![]
+    };
+    let segments: Vec<(usize, usize)> = match window.sentence_lens {
+        Some(lens) => lens.iter().scan(0, |offset, l| {
+            let start = *offset;
+            *offset += l;
+            Some((start, *l))
+        }).collect(),
+        None => vec![(0, raw.len())]
+    };
+    let timer = Instant::now();
+    // pair of window start and its length truncated to the largest gram
+    let mut spans: Vec<(usize, usize)> = segments.into_iter().filter(|(_, l)| *l >= min_gram).flat_map(|(start, l)| {
+        (start..(start + l - min_gram + 1)).step_by(window.stride).map(move |i| (i, (start + l - i).min(max_gram)))
+    }).collect();
+    times.flatten += timer.elapsed();
+    let timer = Instant::now();
+    spans.par_sort_unstable_by(|(a, a_len), (b, b_len)| raw[*a..(*a + a_len)].cmp(&raw[*b..(*b + b_len)]));
+    times.sort += timer.elapsed();
+
+    let timer = Instant::now();
+    let counts = grams.par_iter().map(|gram| {
+        let g = *gram as usize;
+        let mut windows = spans.iter().filter(|(_, l)| *l >= g).map(|(i, _)| &raw[*i..(*i + g)]).peekable();
+        let groups = std::iter::from_fn(|| {
+            let ngram = windows.next()?;
+            let mut count = 1;
+            while windows.next_if_eq(&ngram).is_some() {
+                count += 1;
+            }
+            Some((ngram, count))
+        });
+        collect_groups(groups, max_ngrams)
+    }).collect();
+    times.group += timer.elapsed();
+
+    counts
+}
+
+/// Count number of documents that each n-gram of `counts`, sorted by n-gram, occur in.
+/// Window that span across documents belong to the document that it start in.
+/// 
+/// # Parameter
+/// - `gram` - Number of gram.
+/// - `raw` - Vectorized corpus that `counts` is counted from.
+/// - `window` - Options that control where windows start. It must be the same as one used to count `counts`.
+/// - `document_lens` - Length of each document of `raw`.
+/// - `counts` - Frequency table of n-gram of `raw`. N-gram that is not in it is ignored.
+/// 
+/// # Return
+/// Document frequency of each n-gram in the same order as `counts`.
+pub fn document_frequencies<T: Token>(gram: u8, raw: &[T], window: &WindowOption, document_lens: &[usize], counts: &[(Vec<T>, usize)]) -> Vec<usize> {
+    let g = gram as usize;
+    let doc_ends: Vec<usize> = document_lens.iter().scan(0, |end, l| {
+        *end += l;
+        Some(*end)
+    }).collect();
+    // pair of document and n-gram so each pair is only counted once
+    let mut occurrences: Vec<(usize, usize)> = window_starts(g, raw.len(), window).into_par_iter().filter_map(|i| {
+        let idx = counts.binary_search_by(|(ngram, _)| ngram.as_slice().cmp(&raw[i..(i + g)])).ok()?;
+        Some((doc_ends.partition_point(|end| *end <= i), idx))
+    }).collect();
+    occurrences.par_sort_unstable();
+    occurrences.dedup();
+
+    let mut frequencies = vec![0; counts.len()];
+    for (_, idx) in occurrences {
+        frequencies[idx] += 1;
+    }
+
+    frequencies
+}
+
+/// Compute document frequency of `counted` and store it in `counted`.
+/// See [document_frequencies](fn.document_frequencies.html) for detail of each parameter.
+pub fn add_document_frequency<T: Token>(counted: &mut CountedNgrams<T>, gram: u8, raw: &[T], window: &WindowOption, document_lens: &[usize]) {
+    counted.document_frequency = Some(document_frequencies(gram, raw, window, document_lens, &counted.counts));
+}
+
+/// Count n-gram windows of `raw` that contain `oov_id`, the vectorized char of excluded character.
+/// Unlike [oov_windows](fn.oov_windows.html), every window is counted even if its n-gram is not kept.
+/// 
+/// # Parameter
+/// - `gram` - Number of gram.
+/// - `raw` - Vectorized corpus.
+/// - `window` - Options that control where windows start. See [WindowOption](struct.WindowOption.html).
+/// - `oov_id` - Vectorized char of excluded character.
+pub fn count_oov_windows<T: Token>(gram: u8, raw: &[T], window: &WindowOption, oov_id: Id) -> usize {
+    let g = gram as usize;
+    // number of excluded characters before each position so each window is checked in constant time
+    let mut before = Vec::with_capacity(raw.len() + 1);
+    before.push(0);
+    for t in raw {
+        before.push(before[before.len() - 1] + (t.id() == oov_id) as usize);
+    }
+
+    window_starts(g, raw.len(), window).into_iter().filter(|start| before[start + g] > before[*start]).count()
+}
+
+/// Count n-gram windows of `raw` that contain `oov_id` and store it in `counted`.
+/// See [count_oov_windows](fn.count_oov_windows.html) for detail of each parameter.
+pub fn add_oov_windows<T: Token>(counted: &mut CountedNgrams<T>, gram: u8, raw: &[T], window: &WindowOption, oov_id: Id) {
+    counted.oov_windows = Some(count_oov_windows(gram, raw, window, oov_id));
+}
+
+/// Compute perplexity of `test` under n-gram model built from `train` with add-one smoothing.
+/// Probability of each n-gram window of `test` is its count in `train` plus one divided by
+/// count of its first `gram - 1` characters in `train` plus `vocab`.
+/// 
+/// # Parameter
+/// - `gram` - Number of gram.
+/// - `train` - Vectorized corpus that build n-gram model.
+/// - `train_window` - Options that control where windows of `train` start.
+/// - `test` - Vectorized corpus to be evaluated. It must be vectorized by the same char map as `train`.
+/// - `test_window` - Options that control where windows of `test` start.
+/// - `vocab` - Number of distinct vectorized characters, including those only found in `test`.
+/// 
+/// # Return
+/// None if `test` has no n-gram window. Otherwise, perplexity of `test`.
+pub fn perplexity<T: Token>(gram: u8, train: &[T], train_window: &WindowOption, test: &[T], test_window: &WindowOption, vocab: usize) -> Option<f64> {
+    let g = gram as usize;
+    let counts = count_ngrams(gram, train, train_window);
+    // counts is sorted by n-gram so n-grams sharing the same context are adjacent
+    let mut contexts: Vec<(&[T], usize)> = vec![];
+    for (ngram, count) in counts.iter() {
+        match contexts.last_mut() {
+            Some((context, total)) if *context == &ngram[..(g - 1)] => *total += count,
+            _ => contexts.push((&ngram[..(g - 1)], *count))
+        }
+    }
+    let starts = window_starts(g, test.len(), test_window);
+    if starts.is_empty() {
+        return None
+    }
+
+    let log_prob: f64 = starts.par_iter().map(|i| {
+        let window = &test[*i..(*i + g)];
+        let count = counts.binary_search_by(|(ngram, _)| ngram.as_slice().cmp(window)).map(|idx| counts[idx].1).unwrap_or(0);
+        let context = contexts.binary_search_by(|(context, _)| (*context).cmp(&window[..(g - 1)])).map(|idx| contexts[idx].1).unwrap_or(0);
+        ((count + 1) as f64 / (context + vocab) as f64).ln()
+    }).sum();
+
+    Some((-log_prob / starts.len() as f64).exp())
+}
+
+/// Compute Shannon entropy in bits of n-gram distribution.
+/// An empty `counts` has 0 entropy.
+pub fn ngram_entropy<T>(counts: &[(Vec<T>, usize)]) -> f64 {
+    let total: usize = counts.iter().map(|(_, c)| c).sum();
+    if total == 0 {
+        return 0f64
+    }
+    let total = total as f64;
+
+    -counts.iter().map(|(_, c)| {
+        let p = *c as f64 / total;
+        p * p.log2()
+    }).sum::<f64>()
+}
+
+/// Get `k` most frequent n-grams from `counts` in descending order of frequency.
+/// N-gram with equals frequency are ordered by its vectorized chars.
+/// If `k` is larger than number of n-grams, all n-grams are returned.
+pub fn top_ngrams<T: Token>(counts: &[(Vec<T>, usize)], k: usize) -> Vec<&(Vec<T>, usize)> {
+    let mut sorted: Vec<&(Vec<T>, usize)> = counts.iter().collect();
+    sorted.par_sort_unstable_by(|(a_ngram, a_count), (b_ngram, b_count)| {
+        b_count.cmp(a_count).then_with(|| a_ngram.cmp(b_ngram))
+    });
+    sorted.truncate(k);
+
+    sorted
+}
+
+/// Join vectorized chars of `ngram` by space.
+pub fn format_ids<T: Token>(ngram: &[T]) -> String {
+    ngram.iter().map(|t| t.id().to_string()).collect::<Vec<String>>().join(" ")
+}
+
+/// Join tags of `ngram` by space.
+pub fn format_tags<T: Token>(ngram: &[T]) -> String {
+    ngram.iter().map(|t| t.tag().to_string()).collect::<Vec<String>>().join(" ")
+}
+
+/// For each cumulative coverage threshold in `thresholds`, find how many of the most
+/// frequent n-grams are needed so their total occurrence cover at least that fraction
+/// of all n-gram occurrences.
+/// 
+/// # Parameter
+/// - `counts` - N-gram frequency table.
+/// - `thresholds` - Fraction of total n-gram occurrences, each one is between 0 and 1.
+/// 
+/// # Return
+/// Vec of threshold and number of n-grams needed, in the same order as `thresholds`.
+pub fn coverage<T: Token>(counts: &[(Vec<T>, usize)], thresholds: &[f64]) -> Vec<(f64, usize)> {
+    let total: usize = counts.iter().map(|(_, c)| c).sum();
+    let sorted = top_ngrams(counts, counts.len());
+    let mut cumulative = Vec::with_capacity(sorted.len());
+    sorted.iter().fold(0, |sum, (_, c)| {
+        cumulative.push(sum + c);
+        sum + c
+    });
+
+    thresholds.iter().map(|t| {
+        let needed = (t * total as f64).ceil() as usize;
+        // index of the first n-gram whose cumulative occurrence reach needed
+        let n = cumulative.partition_point(|c| *c < needed);
+        (*t, (n + 1).min(sorted.len()))
+    }).collect()
+}
+
+/// Write coverage table as CSV into `path`.
+pub fn write_coverage(path: &Path, coverage: &[(f64, usize)]) -> csv::Result<()> {
+    let mut writer = csv::Writer::from_path(path)?;
+    writer.write_record(["coverage", "ngrams"])?;
+
+    for (threshold, n) in coverage {
+        writer.write_record(&[threshold.to_string(), n.to_string()])?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Count how many n-grams occur exactly each number of times in `counts`, i.e. frequency of frequencies.
+/// 
+/// # Return
+/// A BTreeMap that map number of occurrence to number of n-grams that occur that many times.
+pub fn freq_of_freq<T>(counts: &[(Vec<T>, usize)]) -> BTreeMap<usize, usize> {
+    let mut fof = BTreeMap::new();
+    for (_, count) in counts {
+        *fof.entry(*count).or_insert(0) += 1;
+    }
+
+    fof
+}
+
+/// Smoothing applied to probability of each n-gram in frequency table.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Smoothing {
+    /// No probability is reported.
+    None,
+    /// Simple Good-Turing estimate. See [good_turing](fn.good_turing.html).
+    GoodTuring
+}
+
+impl FromStr for Smoothing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Smoothing, String> {
+        match s {
+            "none" => Ok(Smoothing::None),
+            "good-turing" => Ok(Smoothing::GoodTuring),
+            _ => Err(format!("Unknown smoothing {}", s))
+        }
+    }
+}
+
+impl fmt::Display for Smoothing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Smoothing::None => f.write_str("none"),
+            Smoothing::GoodTuring => f.write_str("good-turing")
+        }
+    }
+}
+
+/// Good-Turing estimate of n-gram frequency table.
+pub struct GoodTuring {
+    /// Smoothed probability of each n-gram in the same order as its frequency table.
+    pub probabilities: Vec<f64>,
+    /// Total probability reserved for every n-gram that is never seen.
+    pub unseen: f64
+}
+
+/// Estimate probability of each n-gram in `counts` by Simple Good-Turing of Gale and Sampson.
+/// 
+/// Probability mass of unseen n-grams is `N1 / N` where `N1` is number of n-grams that occur once
+/// and `N` is total occurrences. Since some number of occurrence `r` has no n-gram in `fof`,
+/// each `Nr` is first averaged over the gap to its neighbouring non-empty `r` then a line
+/// `log Zr = a + b log r` is fitted by least squares. Adjusted count `r* = (r + 1) S(r + 1) / S(r)`
+/// uses the fitted `S` once it is no longer significantly different from Turing estimate
+/// `(r + 1) N(r+1) / Nr`, and Turing estimate before that. Probability of n-gram that occur
+/// `r` times is `(1 - N1 / N) r* / N'` where `N'` is total of `Nr r*` so probabilities sum to 1.
+/// If `fof` has a single `r`, the line cannot be fitted and `r* = r`. It is also `r* = r`
+/// with a warning if slope `b` is not below -1 since the fitted `S` then doesn't decrease fast enough.
+/// 
+/// # Parameter
+/// - `counts` - Frequency table as returned by [count_ngrams](fn.count_ngrams.html).
+/// - `fof` - Frequency of frequencies of `counts`. See [freq_of_freq](fn.freq_of_freq.html).
+pub fn good_turing<T>(counts: &[(Vec<T>, usize)], fof: &BTreeMap<usize, usize>) -> GoodTuring {
+    let total: f64 = fof.iter().map(|(r, n)| (r * n) as f64).sum();
+    if total == 0f64 {
+        return GoodTuring {probabilities: vec![0f64; counts.len()], unseen: 0f64}
+    }
+    let unseen = fof.get(&1).map(|n| *n as f64 / total).unwrap_or(0f64);
+    let bins: Vec<(f64, f64)> = fof.iter().map(|(r, n)| (*r as f64, *n as f64)).collect();
+    let log_z: Vec<(f64, f64)> = bins.iter().enumerate().map(|(j, (r, n))| {
+        let prev = if j == 0 { 0f64 } else { bins[j - 1].0 };
+        let next = if j + 1 < bins.len() { bins[j + 1].0 } else { 2f64 * r - prev };
+        (r.ln(), (2f64 * n / (next - prev)).ln())
+    }).collect();
+    let len = log_z.len() as f64;
+    let mean_x = log_z.iter().map(|(x, _)| x).sum::<f64>() / len;
+    let mean_y = log_z.iter().map(|(_, y)| y).sum::<f64>() / len;
+    let variance: f64 = log_z.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+    let slope = log_z.iter().map(|(x, y)| (x - mean_x) * (y - mean_y)).sum::<f64>() / variance;
+    let intercept = mean_y - slope * mean_x;
+    let fitted = |r: f64| (intercept + slope * r.ln()).exp();
+    // line that is not steeper than -1 doesn't make r* grow with r
+    let smoothed = bins.len() >= 2 && slope < -1f64;
+    if bins.len() >= 2 && !smoothed {
+        warn!("Fitted slope {} of frequency of frequencies is not below -1 so Good-Turing use unsmoothed counts", slope);
+    }
+
+    let mut use_fitted = false;
+    let adjusted: BTreeMap<usize, f64> = fof.iter().map(|(r, n)| {
+        let (r_f, n) = (*r as f64, *n as f64);
+        if !smoothed {
+            return (*r, r_f)
+        }
+        let y = (r_f + 1f64) * fitted(r_f + 1f64) / fitted(r_f);
+        if !use_fitted {
+            match fof.get(&(r + 1)) {
+                Some(next) => {
+                    let next = *next as f64;
+                    let x = (r_f + 1f64) * next / n;
+                    let deviation = 1.96 * ((r_f + 1f64).powi(2) * next / n.powi(2) * (1f64 + next / n)).sqrt();
+                    if (x - y).abs() > deviation {
+                        return (*r, x)
+                    }
+                    use_fitted = true;
+                },
+                None => use_fitted = true
+            }
+        }
+        (*r, y)
+    }).collect();
+    let adjusted_total: f64 = fof.iter().map(|(r, n)| *n as f64 * adjusted[r]).sum();
+    let probabilities = counts.iter().map(|(_, c)| (1f64 - unseen) * adjusted[c] / adjusted_total).collect();
+
+    GoodTuring {probabilities, unseen}
+}
+
+/// Write frequency of frequencies as returned by [freq_of_freq](fn.freq_of_freq.html) as CSV into `path`.
+pub fn write_freq_of_freq(path: &Path, fof: &BTreeMap<usize, usize>) -> csv::Result<()> {
+    let mut writer = csv::Writer::from_path(path)?;
+    writer.write_record(["count", "ngrams"])?;
+
+    for (count, ngrams) in fof {
+        writer.write_record(&[count.to_string(), ngrams.to_string()])?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Parse comma separated list of coverage threshold. Each must be in range (0, 1].
+pub fn parse_coverage_thresholds(thresholds: &str) -> Result<Vec<f64>, String> {
+    thresholds.split(',').map(|t| {
+        match t.trim().parse::<f64>() {
+            Ok(v) if v > 0f64 && v <= 1f64 => Ok(v),
+            _ => Err(format!("Coverage threshold must be a number in range (0, 1] but found \"{}\"", t))
+        }
+    }).collect()
+}
+
+/// Format of n-gram frequency table.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Csv,
+    Json
+}
+
+impl OutputFormat {
+    /// File extension of this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::Json => "json"
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OutputFormat, String> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("Unknown output format {}", s))
+        }
+    }
+}
+
+/// Parse CSV field delimiter. It must be a single ASCII character other than quote or newline.
+/// `\t` is accepted as tab.
+pub fn parse_delimiter(delimiter: &str) -> Result<u8, String> {
+    match delimiter {
+        "\\t" => Ok(b'\t'),
+        d if d.len() == 1 && d.is_ascii() && !matches!(d, "\"" | "\n" | "\r") => Ok(d.as_bytes()[0]),
+        _ => Err(format!("Delimiter must be a single ASCII character other than quote or newline but found \"{}\"", delimiter))
+    }
+}
+
+/// Decode vectorized chars of `ngram` back to actual chars using `rev_map`.
+/// `ngram` is either vectorized chars alone or vectorized chars with tag.
+/// Vectorized char that is not in `rev_map`, e.g. excluded or OOV char, is decoded to `\u{FFFD}`.
+pub fn decode_ngram<T: Token>(ngram: &[T], rev_map: &HashMap<Id, char>) -> String {
+    ngram.iter().map(|t| *rev_map.get(&t.id()).unwrap_or(&'\u{FFFD}')).collect()
+}
+
+/// Header of CSV n-gram frequency table. See [write_ngram_counts](fn.write_ngram_counts.html).
+fn csv_header<T: Token>(with_chars: bool, columns: ExtraColumns) -> Vec<&'static str> {
+    let mut header = vec!["ngram"];
+    if T::TAGGED {
+        header.push("tags");
+    }
+    if with_chars {
+        header.push("chars");
+    }
+    header.push("count");
+    if columns.document_frequency.is_some() {
+        header.push("document_frequency");
+    }
+    if columns.probability.is_some() {
+        header.push("probability");
+    }
+
+    header
+}
+
+/// A row of CSV n-gram frequency table. Decoded chars are included if `rev_map` is given,
+/// document frequency is included if `document_frequency` is given and probability is
+/// included if `probability` is given.
+fn csv_row<T: Token>(ngram: &[T], count: usize, rev_map: Option<&HashMap<Id, char>>, document_frequency: Option<usize>, probability: Option<f64>) -> Vec<String> {
+    let mut row = vec![format_ids(ngram)];
+    if T::TAGGED {
+        row.push(format_tags(ngram));
+    }
+    if let Some(rev_map) = rev_map {
+        row.push(decode_ngram(ngram, rev_map));
+    }
+    row.push(count.to_string());
+    if let Some(df) = document_frequency {
+        row.push(df.to_string());
+    }
+    if let Some(p) = probability {
+        row.push(p.to_string());
+    }
+
+    row
+}
+
+/// Optional columns of n-gram frequency table. Each is in the same order as its n-grams.
+#[derive(Clone, Copy, Default)]
+pub struct ExtraColumns<'a> {
+    /// Number of documents that each n-gram occur in.
+    pub document_frequency: Option<&'a [usize]>,
+    /// Smoothed probability of each n-gram. See [good_turing](fn.good_turing.html).
+    pub probability: Option<&'a [f64]>
+}
+
+impl<'a> ExtraColumns<'a> {
+    /// Columns of n-grams in `range` only.
+    fn slice(self, range: Range<usize>) -> ExtraColumns<'a> {
+        ExtraColumns {
+            document_frequency: self.document_frequency.map(|df| &df[range.clone()]),
+            probability: self.probability.map(|p| &p[range])
+        }
+    }
+}
+
+/// Write n-gram frequency table into `writer` in given `format`.
+/// 
+/// In CSV format, fields are separated by `delimiter` and quoted as per RFC 4180 whenever
+/// it contain delimiter, quote, or newline. Each n-gram is written as space separated vectorized chars.
+/// If n-gram carry tag, there is an additional column of space separated tags.
+/// If `with_chars` is true, there is an additional `chars` column of decoded n-gram.
+/// If document frequency or probability of `columns` is given, there is an additional
+/// `document_frequency` or `probability` column respectively.
+/// 
+/// In JSON format, it is an array of object with `ngram`, `chars`, and `count` field.
+/// If n-gram carry tag, there is an additional `tags` field and each given column of
+/// `columns` is an additional field of the same name. Each object is
+/// written as soon as it is serialized so the entire table never need to be
+/// formatted in memory.
+pub fn write_ngram_counts<'a, T, W, I>(writer: W, counts: I, format: OutputFormat, delimiter: u8, rev_map: &HashMap<Id, char>, with_chars: bool, columns: ExtraColumns) -> std::io::Result<()> 
+where T: Token + 'a, W: Write, I: IntoIterator<Item = &'a (Vec<T>, usize)> {
+    match format {
+        OutputFormat::Csv => {
+            let mut writer = csv::WriterBuilder::new().delimiter(delimiter).from_writer(writer);
+            writer.write_record(csv_header::<T>(with_chars, columns))?;
+
+            for (i, (ngram, count)) in counts.into_iter().enumerate() {
+                let rev_map = if with_chars { Some(rev_map) } else { None };
+                writer.write_record(csv_row(ngram, *count, rev_map, columns.document_frequency.map(|df| df[i]), columns.probability.map(|p| p[i])))?;
+            }
+
+            writer.flush()
+        },
+        OutputFormat::Json => {
+            let mut writer = BufWriter::new(writer);
+            writer.write_all(b"[")?;
+
+            for (i, (ngram, count)) in counts.into_iter().enumerate() {
+                if i > 0 {
+                    writer.write_all(b",")?;
+                }
+                let mut row = serde_json::json!({
+                    "ngram": ngram.iter().map(|t| t.id()).collect::<Vec<Id>>(),
+                    "chars": decode_ngram(ngram, rev_map),
+                    "count": count
+                });
+                if T::TAGGED {
+                    row["tags"] = serde_json::json!(ngram.iter().map(|t| t.tag()).collect::<Vec<Tag>>());
+                }
+                if let Some(df) = columns.document_frequency {
+                    row["document_frequency"] = serde_json::json!(df[i]);
+                }
+                if let Some(p) = columns.probability {
+                    row["probability"] = serde_json::json!(p[i]);
+                }
+                serde_json::to_writer(&mut writer, &row)?;
+            }
+
+            writer.write_all(b"]\n")?;
+            writer.flush()
+        }
+    }
+}
+
+/// Number of n-gram formatted by each rayon task when writing CSV frequency table.
+const CSV_CHUNK_ROWS: usize = 16384;
+
+/// Format CSV rows of `counts` into a buffer. It is the same as rows written by
+/// [write_ngram_counts](fn.write_ngram_counts.html) without header.
+fn format_csv_rows<T: Token>(counts: &[(Vec<T>, usize)], delimiter: u8, rev_map: Option<&HashMap<Id, char>>, columns: ExtraColumns) -> csv::Result<Vec<u8>> {
+    let mut writer = csv::WriterBuilder::new().has_headers(false).delimiter(delimiter).from_writer(vec![]);
+    for (i, (ngram, count)) in counts.iter().enumerate() {
+        writer.write_record(csv_row(ngram, *count, rev_map, columns.document_frequency.map(|df| df[i]), columns.probability.map(|p| p[i])))?;
+    }
+
+    writer.into_inner().map_err(|err| std::io::Error::new(err.error().kind(), err.to_string()).into())
+}
+
+/// Write n-gram frequency table into `writer` in given `format`.
+/// It produce the same output as [write_ngram_counts](fn.write_ngram_counts.html) but in CSV format,
+/// rows are formatted in chunks across rayon threads then written in order.
+/// Only a batch of chunks, one per thread, is held in memory at a time.
+pub fn write_ngram_table<T, W>(writer: W, counts: &[(Vec<T>, usize)], format: OutputFormat, delimiter: u8, rev_map: &HashMap<Id, char>, with_chars: bool, columns: ExtraColumns) -> std::io::Result<()>
+where T: Token, W: Write {
+    if format != OutputFormat::Csv {
+        return write_ngram_counts(writer, counts, format, delimiter, rev_map, with_chars, columns)
+    }
+
+    let mut writer = BufWriter::new(writer);
+    let mut header = csv::WriterBuilder::new().delimiter(delimiter).from_writer(vec![]);
+    header.write_record(csv_header::<T>(with_chars, columns))?;
+    writer.write_all(&header.into_inner().map_err(|err| std::io::Error::new(err.error().kind(), err.to_string()))?)?;
+
+    write_csv_rows(writer, counts, delimiter, if with_chars { Some(rev_map) } else { None }, columns)
+}
+
+/// Append CSV n-gram frequency table to `path` instead of overwriting it.
+/// Header is only written if `path` doesn't exist or is empty so rows of every run
+/// form a single table. Rows are the same as [write_ngram_table](fn.write_ngram_table.html).
+pub fn append_ngram_table<T: Token>(path: &Path, counts: &[(Vec<T>, usize)], delimiter: u8, rev_map: &HashMap<Id, char>, with_chars: bool, columns: ExtraColumns) -> std::io::Result<()> {
+    let file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+    if file.metadata()?.len() == 0 {
+        return write_ngram_table(file, counts, OutputFormat::Csv, delimiter, rev_map, with_chars, columns)
+    }
+
+    write_csv_rows(BufWriter::new(file), counts, delimiter, if with_chars { Some(rev_map) } else { None }, columns)
+}
+
+/// Write CSV rows of `counts` into `writer` without header in chunks formatted across rayon threads.
+fn write_csv_rows<T, W>(mut writer: BufWriter<W>, counts: &[(Vec<T>, usize)], delimiter: u8, rev_map: Option<&HashMap<Id, char>>, columns: ExtraColumns) -> std::io::Result<()>
+where T: Token, W: Write {
+    let batch_rows = CSV_CHUNK_ROWS * rayon::current_num_threads();
+    for (b, batch) in counts.chunks(batch_rows).enumerate() {
+        let chunks = batch.par_chunks(CSV_CHUNK_ROWS).enumerate().map(|(c, chunk)| {
+            let start = b * batch_rows + c * CSV_CHUNK_ROWS;
+            format_csv_rows(chunk, delimiter, rev_map, columns.slice(start..(start + chunk.len())))
+        }).collect::<csv::Result<Vec<Vec<u8>>>>()?;
+        for chunk in chunks {
+            writer.write_all(&chunk)?;
+        }
+    }
+
+    writer.flush()
+}
+
+/// Number of bytes occupied by elements of `v`.
+pub fn vec_bytes<T>(v: &[T]) -> usize {
+    std::mem::size_of_val(v)
+}
+
+/// Estimated number of bytes of sorted windows buffer used to count `windows` n-gram
+/// windows. Each window is stored as its start index.
+pub fn ngram_buffer_bytes(windows: usize) -> usize {
+    windows * std::mem::size_of::<usize>()
+}
+
+/// Peak resident memory of this process in bytes, read from `VmHWM` of `/proc/self/status`.
+/// 
+/// # Return
+/// None if it is unavailable, e.g. the platform has no `/proc`.
+pub fn peak_rss() -> Option<usize> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kb = line.trim_start_matches("VmHWM:").trim().trim_end_matches("kB").trim().parse::<usize>().ok()?;
+
+    Some(kb * 1024)
+}
+
+/// Estimated number of bytes of char map that has `len` entries, ignoring hashing overhead.
+pub fn char_map_bytes(len: usize) -> usize {
+    len * (std::mem::size_of::<char>() + std::mem::size_of::<Id>())
+}
+
+/// Format `bytes` into human readable unit, e.g. `1.5 KiB`.
+pub fn format_bytes(bytes: usize) -> String {
+    let (value, unit) = bytify(bytes as u64);
+    format!("{} {}", value, unit)
+}
+
+/// Line reported by an analysis. Lines are collected instead of printed right away so
+/// analysis of several grams can run concurrently without interleaving their output.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReportLine {
+    /// Result of analysis. It is printed to stdout.
+    Result(String),
+    /// Diagnostic message. It is logged at its level to stderr.
+    Log(Level, String)
+}
+
+impl ReportLine {
+    /// Print this line to stdout if it is a result or log it otherwise.
+    pub fn emit(&self) {
+        match self {
+            ReportLine::Result(line) => println!("{}", line),
+            ReportLine::Log(level, line) => log::log!(*level, "{}", line)
+        }
+    }
+}
+
+/// Options that control how result of n-gram analysis is reported.
+#[derive(Clone)]
+pub struct ReportOption {
+    /// Number of most frequent n-grams to be printed.
+    pub top: Option<usize>,
+    /// Vectorized char of excluded character, if it is kept in vectorized corpus.
+    pub oov_id: Option<Id>,
+    /// Maximum number of most frequent n-gram kept when counting. Every n-gram is kept if it is `None`.
+    pub max_ngrams: Option<usize>,
+    /// N-gram that occur less than this is excluded from frequency table and top-K report.
+    pub min_count: usize,
+    /// Cumulative coverage thresholds to be printed.
+    pub coverage: Vec<f64>,
+    /// A HashMap that map vectorized char back to actual char.
+    pub rev_map: HashMap<Id, char>,
+    /// File to store n-gram frequency table.
+    pub out_path: PathBuf,
+    /// Optional extra file to store the same n-gram frequency table. It is appended to as well
+    /// if `append` is true. It is ignored if the table is sharded.
+    pub copy_path: Option<PathBuf>,
+    /// Optional CSV file to store coverage table.
+    pub coverage_path: Option<PathBuf>,
+    /// Optional CSV file to store frequency of frequencies.
+    pub fof_path: Option<PathBuf>,
+    /// Format of n-gram frequency table and top-K report.
+    pub format: OutputFormat,
+    /// Field delimiter of CSV n-gram frequency table.
+    pub delimiter: u8,
+    /// If true, CSV n-gram frequency table has decoded chars column.
+    pub with_chars: bool,
+    /// Length of each document of analyzed corpus. If it is given, n-gram frequency table
+    /// has document frequency of each n-gram.
+    pub document_lens: Option<Vec<usize>>,
+    /// If true, n-gram frequency table is split into a file per first vectorized char
+    /// next to `out_path`. See [shard_by_prefix](fn.shard_by_prefix.html).
+    pub shard_by_prefix: bool,
+    /// Smoothing of probability column of n-gram frequency table.
+    pub smoothing: Smoothing,
+    /// If true, CSV n-gram frequency table is appended to existing file instead of overwriting it.
+    /// See [append_ngram_table](fn.append_ngram_table.html).
+    pub append: bool
+}
+
+/// Summary of n-gram analysis of a single gram.
+pub struct NgramSummary {
+    pub gram: u8,
+    /// Number of unique n-gram.
+    pub unique: usize,
+    /// Number of n-gram windows.
+    pub windows: usize,
+    /// Number of n-gram windows that contain excluded character if excluded character is kept.
+    pub oov_windows: Option<usize>,
+    /// Shannon entropy in bits of n-gram distribution.
+    pub entropy: f64,
+    /// Time spent on each stage of counting n-gram. If grams are counted in a combined pass,
+    /// time of the pass is split evenly among them.
+    pub count_times: CountTimes,
+    /// Time spent writing n-gram frequency table.
+    pub write_time: Duration,
+    /// Estimated number of bytes of n-gram buffer used during analysis.
+    pub buffer_bytes: usize,
+    /// Perplexity of test corpus if it is evaluated.
+    pub perplexity: Option<f64>,
+    /// Length of the longest sentence if gram is longer than every sentence so no window
+    /// fit in any sentence. See [gram_exceeds_sentences](fn.gram_exceeds_sentences.html).
+    pub exceeds_sentences: Option<usize>
+}
+
+/// Check whether `gram` is longer than every sentence when windows respect
+/// `sentence_lens` of `window`, in which case no n-gram can be counted.
+/// 
+/// # Return
+/// Length of the longest sentence if `gram` is longer than it. None if `gram` fit in
+/// some sentence or windows don't respect sentence.
+pub fn gram_exceeds_sentences(gram: u8, window: &WindowOption) -> Option<usize> {
+    let longest = window.sentence_lens?.iter().cloned().max().unwrap_or(0);
+    if gram as usize > longest {
+        Some(longest)
+    } else {
+        None
+    }
+}
+
+/// Run n-gram analysis on `raw` then report the result to `log` and `out_path`.
+/// 
+/// # Parameter
+/// - `gram` - Number of gram.
+/// - `raw` - Vectorized corpus. Either vectorized chars alone or vectorized chars with tag.
+/// - `window` - Options that control where windows start. See [WindowOption](struct.WindowOption.html).
+/// - `option` - Options that control how the result is reported.
+/// - `log` - Each line to be reported is appended to it so analysis of
+///   several grams can run concurrently without interleaving their output. See [ReportLine](enum.ReportLine.html).
+/// 
+/// # Return
+/// Summary of the analysis. See [NgramSummary](struct.NgramSummary.html).
+/// Err if any output file cannot be written.
+pub fn analyze_ngrams<T: Token>(gram: u8, raw: &[T], window: &WindowOption, option: &ReportOption, log: &mut Vec<ReportLine>) -> std::io::Result<NgramSummary> {
+    let mut times = CountTimes::default();
+    let mut counted = count_ngrams_timed(gram, raw, window, option.max_ngrams, &mut times);
+    if let Some(lens) = &option.document_lens {
+        add_document_frequency(&mut counted, gram, raw, window, lens);
+    }
+    // kept n-grams no longer tell every window that contain excluded character
+    if let (Some(oov_id), Some(_)) = (option.oov_id, option.max_ngrams) {
+        add_oov_windows(&mut counted, gram, raw, window, oov_id);
+    }
+
+    report_ngrams(gram, &counted, times, option, log)
+}
+
+/// Shard of n-gram frequency table. It is the first vectorized char shared by every n-gram
+/// in the shard and the n-grams with their number of occurrence.
+pub type NgramShard<'a, T> = (Id, &'a [(Vec<T>, usize)]);
+
+/// Split n-gram frequency table `counts`, which is sorted by n-gram, into consecutive shards
+/// of n-grams that share the same first vectorized char.
+/// 
+/// # Return
+/// Pair of first vectorized char and its shard, ordered by first vectorized char.
+pub fn shard_by_prefix<T: Token>(counts: &[(Vec<T>, usize)]) -> Vec<NgramShard<'_, T>> {
+    let mut shards = vec![];
+    let mut rest = counts;
+    while let Some((first, _)) = rest.first() {
+        let prefix = first[0].id();
+        let end = rest.iter().position(|(ngram, _)| ngram[0].id() != prefix).unwrap_or(rest.len());
+        let (shard, remain) = rest.split_at(end);
+        shards.push((prefix, shard));
+        rest = remain;
+    }
+
+    shards
+}
+
+/// Count n-gram windows in `counts` that contain `oov_id`, the vectorized char of excluded character.
+pub fn oov_windows<T: Token>(counts: &[(Vec<T>, usize)], oov_id: Id) -> usize {
+    counts.par_iter().filter(|(ngram, _)| ngram.iter().any(|t| t.id() == oov_id)).map(|(_, c)| c).sum()
+}
+
+/// Exclude n-gram that occur less than `min_count` times out of `counts`.
+/// `counts` is borrowed as is if `min_count` is at most 1.
+pub fn filter_min_count<T: Clone>(counts: &[(Vec<T>, usize)], min_count: usize) -> Cow<'_, [(Vec<T>, usize)]> {
+    if min_count <= 1 {
+        Cow::Borrowed(counts)
+    } else {
+        Cow::Owned(counts.iter().filter(|(_, c)| *c >= min_count).cloned().collect())
+    }
+}
+
+/// Report n-gram frequency table `counted` that took `count_times` to count to `log` and `out_path`.
+/// If only the most frequent n-grams are kept in `counted`, entropy and coverage are computed
+/// from kept n-grams only. Excluded character windows are taken from `oov_windows` of `counted`
+/// if it is counted, otherwise they are computed from kept n-grams.
+/// See [analyze_ngrams](fn.analyze_ngrams.html) for detail of each parameter.
+pub fn report_ngrams<T: Token>(gram: u8, counted: &CountedNgrams<T>, count_times: CountTimes, option: &ReportOption, log: &mut Vec<ReportLine>) -> std::io::Result<NgramSummary> {
+    let rev_map = &option.rev_map;
+    let format = option.format;
+    let counts = counted.counts.as_slice();
+    let windows = counted.windows;
+    let entropy = ngram_entropy(counts);
+    log.push(ReportLine::Log(Level::Debug, format!("Total unique analysis time is {}s", count_times.total().as_secs())));
+    log.push(ReportLine::Result(format!("Total {} unique {}-gram", counted.unique, gram)));
+    if counts.len() < counted.unique {
+        log.push(ReportLine::Log(Level::Info, format!("Only {} most frequent {}-gram are kept", counts.len(), gram)));
+    }
+    log.push(ReportLine::Result(format!("{}-gram entropy is {} bits", gram, entropy)));
+    let oov_windows = option.oov_id.map(|oov_id| counted.oov_windows.unwrap_or_else(|| oov_windows(counts, oov_id)));
+    if let Some(oov_windows) = oov_windows {
+        let percent = if windows == 0 { 0f64 } else { oov_windows as f64 * 100f64 / windows as f64 };
+        log.push(ReportLine::Result(format!("{} out of {} {}-gram windows ({:.2}%) contain excluded character", oov_windows, windows, gram, percent)));
+    }
+    let all_counts = counts;
+    let counts: &[(Vec<T>, usize)] = &filter_min_count(counts, option.min_count);
+    // document frequency is filtered along with counts so they stay in the same order
+    let document_frequency: Option<Cow<[usize]>> = counted.document_frequency.as_ref().map(|df| {
+        if option.min_count <= 1 {
+            Cow::Borrowed(df.as_slice())
+        } else {
+            Cow::Owned(all_counts.iter().zip(df).filter(|((_, c), _)| *c >= option.min_count).map(|(_, d)| *d).collect())
+        }
+    });
+    let document_frequency = document_frequency.as_deref();
+    // probability is estimated from every kept n-gram then filtered along with counts
+    let probability: Option<Vec<f64>> = match option.smoothing {
+        Smoothing::GoodTuring => {
+            let estimate = good_turing(all_counts, &freq_of_freq(all_counts));
+            log.push(ReportLine::Result(format!("{}-gram Good-Turing probability of unseen n-grams is {}", gram, estimate.unseen)));
+            Some(all_counts.iter().zip(estimate.probabilities).filter(|((_, c), _)| *c >= option.min_count).map(|(_, p)| p).collect())
+        },
+        Smoothing::None => None
+    };
+    let columns = ExtraColumns {document_frequency, probability: probability.as_deref()};
+    if option.min_count > 1 {
+        log.push(ReportLine::Result(format!("Total {} unique {}-gram occur at least {} times", counts.len(), gram, option.min_count)));
+    }
+
+    if !option.coverage.is_empty() {
+        let coverage = coverage(all_counts, &option.coverage);
+        log.push(ReportLine::Result(format!("{:>10} {:>10}", "coverage", format!("{}-grams", gram))));
+        for (threshold, n) in coverage.iter() {
+            log.push(ReportLine::Result(format!("{:>10} {:>10}", threshold, n)));
+        }
+        if let Some(path) = &option.coverage_path {
+            write_coverage(path, &coverage).map_err(|err| write_error("coverage", path, err))?;
+            log.push(ReportLine::Log(Level::Info, format!("Store {}-gram coverage to {}", gram, path.display())));
+        }
+    }
+
+    if let Some(path) = &option.fof_path {
+        write_freq_of_freq(path, &freq_of_freq(all_counts)).map_err(|err| write_error("frequency of frequencies", path, err))?;
+        log.push(ReportLine::Log(Level::Info, format!("Store {}-gram frequency of frequencies to {}", gram, path.display())));
+    }
+
+    if let Some(k) = option.top {
+        log.push(ReportLine::Result(format!("Top {} {}-gram", k, gram)));
+        let top_counts = top_ngrams(counts, k);
+        match format {
+            OutputFormat::Csv => {
+                for (ngram, count) in top_counts {
+                    let chars = decode_ngram(ngram, rev_map);
+                    if T::TAGGED {
+                        log.push(ReportLine::Result(format!("[{}]\t[{}]\t{}\t{}", format_ids(ngram), format_tags(ngram), chars, count)));
+                    } else {
+                        log.push(ReportLine::Result(format!("[{}]\t{}\t{}", format_ids(ngram), chars, count)));
+                    }
+                }
+            },
+            OutputFormat::Json => {
+                let mut top_json = vec![];
+                write_ngram_counts(&mut top_json, top_counts, format, option.delimiter, rev_map, option.with_chars, ExtraColumns::default())?;
+                log.push(ReportLine::Result(String::from_utf8_lossy(&top_json).trim_end().to_owned()));
+            }
+        }
+    }
+
+    let timer = Instant::now();
+    let write_time;
+    if option.shard_by_prefix {
+        let shards = shard_by_prefix(counts);
+        let mut offset = 0;
+        for (prefix, shard) in shards.iter() {
+            let path = suffixed_path(&option.out_path, &format!("prefix_{}", prefix));
+            let shard_columns = columns.slice(offset..(offset + shard.len()));
+            if option.append {
+                append_ngram_table(&path, shard, option.delimiter, rev_map, option.with_chars, shard_columns)
+            } else {
+                write_atomically(&path, |out_file| {
+                    write_ngram_table(out_file, shard, format, option.delimiter, rev_map, option.with_chars, shard_columns)
+                })
+            }.map_err(|err| write_error("analysis result", &path, err))?;
+            offset += shard.len();
+        }
+        write_time = timer.elapsed();
+        log.push(ReportLine::Log(Level::Debug, format!("Writing {}-gram table took {} ms", gram, write_time.as_millis())));
+        log.push(ReportLine::Log(Level::Info, format!("Store {}-gram to {} shards next to {}", gram, shards.len(), option.out_path.display())));
+    } else {
+        for path in std::iter::once(&option.out_path).chain(&option.copy_path) {
+            if option.append {
+                append_ngram_table(path, counts, option.delimiter, rev_map, option.with_chars, columns)
+            } else {
+                write_atomically(path, |out_file| {
+                    write_ngram_table(out_file, counts, format, option.delimiter, rev_map, option.with_chars, columns)
+                })
+            }.map_err(|err| write_error("analysis result", path, err))?;
+            log.push(ReportLine::Log(Level::Info, format!("Store {}-gram to {}", gram, path.display())));
+        }
+        write_time = timer.elapsed();
+        log.push(ReportLine::Log(Level::Debug, format!("Writing {}-gram table took {} ms", gram, write_time.as_millis())));
+    }
+
+    Ok(NgramSummary {
+        gram,
+        unique: counted.unique,
+        windows,
+        oov_windows,
+        entropy,
+        count_times,
+        write_time,
+        buffer_bytes: ngram_buffer_bytes(windows),
+        perplexity: None,
+        exceeds_sentences: None
+    })
+}
+
+/// Everything needed by [analyze](fn.analyze.html) to vectorize corpus and count its n-grams.
+/// Start from [AnalyzeConfig::new](struct.AnalyzeConfig.html#method.new) and override fields as needed.
+pub struct AnalyzeConfig {
+    /// Number of gram of each frequency table. Corpus is only vectorized if it is empty.
+    pub grams: Vec<u8>,
+    /// Corpus files to be analyzed. `-` is standard input.
+    pub corpuses: Vec<PathBuf>,
+    /// Options that control how corpus files are read.
+    pub read: ReadOption,
+    /// Options that control which character get vectorized and how.
+    pub vectorize: VectorizeOption,
+    /// Which boundaries n-gram windows may not cross.
+    pub boundaries: Boundaries,
+    /// Distance between start of two consecutive windows. It must be at least 1.
+    pub stride: usize,
+    /// If true, tag of each character is part of n-gram.
+    pub with_tags: bool,
+    /// Maximum number of most frequent n-gram kept of each gram. Every n-gram is kept if it is `None`.
+    pub max_ngrams: Option<usize>,
+    /// If true, document frequency of each n-gram is also counted.
+    pub document_frequency: bool,
+    /// If true, repeated sentences are counted only once.
+    /// See [drop_repeated_sentences](fn.drop_repeated_sentences.html).
+    pub drop_repeated_sentences: bool,
+    /// Optional checkpoint file to resume vectorization from and to record progress to.
+    pub checkpoint: Option<PathBuf>,
+    /// If true, number of unique n-gram of each gram is recounted by
+    /// [brute_force_unique](fn.brute_force_unique.html) and analysis fail on mismatch.
+    pub verify: bool,
+    /// Progress of vectorized corpus files.
+    pub progress: ProgressBar,
+    /// Set once the run is requested to stop. Corpus file that is not started yet is not vectorized
+    /// and analysis fail with [AnalyzeError::Interrupted](enum.AnalyzeError.html#variant.Interrupted).
+    pub interrupted: Arc<AtomicBool>,
+    /// Progress of counted grams. It is ticked while counting and incremented once a gram is counted.
+    pub count_progress: ProgressBar
+}
+
+impl AnalyzeConfig {
+    /// Config that analyze `grams` of `corpuses` with the same defaults as command line tool,
+    /// i.e. JSON corpus in UTF-8 where only Thai characters are vectorized.
+    pub fn new(grams: Vec<u8>, corpuses: Vec<PathBuf>) -> AnalyzeConfig {
+        AnalyzeConfig {
+            grams,
+            corpuses,
+            read: ReadOption {buf_size: 16 * 1024 * 1024, format: InputFormat::Json, encoding: Encoding::Utf8, strict: false},
+            vectorize: VectorizeOption {
+                char_include_list: vec![],
+                unicode_ranges: vec![0x0E01..=0x0E7F],
+                equiv_classes: EquivClasses::default(),
+                oov_mode: OovMode::KeepPosition,
+                normalization: Normalization::None,
+                fold_case: false,
+                id_width: IdWidth::U32,
+                tag_width: TagWidth::U8,
+                tag_map: HashMap::new(),
+                tag_filter: TagFilter::All,
+                sample: None,
+                doc_range: None
+            },
+            boundaries: Boundaries::Ignore,
+            stride: 1,
+            with_tags: false,
+            max_ngrams: None,
+            document_frequency: false,
+            drop_repeated_sentences: false,
+            checkpoint: None,
+            verify: false,
+            progress: ProgressBar::hidden(),
+            interrupted: Arc::new(AtomicBool::new(false)),
+            count_progress: ProgressBar::hidden()
+        }
+    }
+}
+
+/// Frequency table of n-gram of either vectorized chars alone or vectorized chars with tag.
+pub enum NgramTable {
+    Chars(CountedNgrams<Id>),
+    Tagged(CountedNgrams<(Id, Tag)>)
+}
+
+/// Frequency table of a single gram produced by [analyze](fn.analyze.html).
+pub struct GramAnalysis {
+    pub gram: u8,
+    pub table: NgramTable,
+    /// Shannon entropy in bits of kept n-grams.
+    pub entropy: f64,
+    /// Time spent on each stage of counting. If grams are counted in a combined pass,
+    /// time of the pass is split evenly among them.
+    pub count_times: CountTimes
+}
+
+/// Result of [analyze](fn.analyze.html).
+pub struct AnalysisResult {
+    /// Map of each vectorized character to its vectorized char.
+    pub char_map: HashMap<char, Id>,
+    /// Next vectorized char to be assigned to a new character.
+    pub next_id: Id,
+    /// Vectorized corpus. Repeated sentences are already removed if config drop them.
+    pub vectorized: Vectorized,
+    /// Repeated sentences that are removed from `vectorized` with their number of occurrence
+    /// before removal. It is empty if config doesn't drop repeated sentences.
+    pub dropped_sentences: Vec<(Vec<Id>, usize)>,
+    /// Frequency table of each gram in the same order as `grams` of config.
+    pub ngrams: Vec<GramAnalysis>,
+    /// Time spent on reading and vectorizing corpus.
+    pub parse_time: Duration
+}
+
+/// Reason that [analyze](fn.analyze.html) fail.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AnalyzeError {
+    /// Config is invalid, e.g. stride is 0.
+    Config(String),
+    /// Checkpoint cannot be loaded.
+    Checkpoint(String),
+    /// Corpus cannot be read or vectorized.
+    Vectorize(String),
+    /// Vectorization is interrupted by user. Completed corpus files are kept in checkpoint, if any.
+    Interrupted,
+    /// Number of unique n-gram doesn't match the brute force recount.
+    Verify(String)
+}
+
+impl fmt::Display for AnalyzeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AnalyzeError::Config(err) | AnalyzeError::Checkpoint(err) | AnalyzeError::Vectorize(err) | AnalyzeError::Verify(err) => write!(f, "{}", err),
+            AnalyzeError::Interrupted => write!(f, "Vectorization is interrupted")
+        }
+    }
+}
+
+impl From<AnalyzeError> for RunError {
+    fn from(err: AnalyzeError) -> RunError {
+        match err {
+            AnalyzeError::Interrupted => RunError::Interrupted,
+            err => RunError::Fatal(err.to_string())
+        }
+    }
+}
+
+/// Count every gram of `config` on `raw`. Multiple grams are counted in a single combined pass.
+/// 
+/// # Return
+/// Err if `verify` of `config` is true and number of unique n-gram of any gram doesn't match the brute force recount.
+fn count_grams<T: Token>(config: &AnalyzeConfig, raw: &[T], window: &WindowOption, document_lens: &[usize]) -> Result<Vec<(CountedNgrams<T>, CountTimes)>, AnalyzeError> {
+    let mut times = CountTimes::default();
+    let mut counted = if config.grams.len() > 1 {
+        let counted = count_ngrams_combined(&config.grams, raw, window, config.max_ngrams, &mut times);
+        config.count_progress.inc(config.grams.len() as u64);
+        let times = times / config.grams.len() as u32;
+        counted.into_iter().map(|c| (c, times)).collect()
+    } else {
+        config.grams.iter().map(|gram| {
+            let counted = count_ngrams_timed(*gram, raw, window, config.max_ngrams, &mut times);
+            config.count_progress.inc(1);
+            (counted, times)
+        }).collect::<Vec<_>>()
+    };
+    if config.document_frequency {
+        for ((c, _), gram) in counted.iter_mut().zip(&config.grams) {
+            add_document_frequency(c, *gram, raw, window, document_lens);
+        }
+    }
+    // kept n-grams no longer tell every window that contain excluded character
+    if let (Some(oov_id), Some(_)) = (config.vectorize.oov_mode.oov_id(config.vectorize.id_width), config.max_ngrams) {
+        for ((c, _), gram) in counted.iter_mut().zip(&config.grams) {
+            add_oov_windows(c, *gram, raw, window, oov_id);
+        }
+    }
+    if config.verify {
+        for ((c, _), gram) in counted.iter().zip(&config.grams) {
+            let expected = brute_force_unique(*gram, raw, window);
+            if c.unique != expected {
+                return Err(AnalyzeError::Verify(format!("Found {} unique {}-gram but brute force recount found {}", c.unique, gram, expected)))
+            }
+            info!("Verified {} unique {}-gram against brute force recount", expected, gram);
+        }
+    }
+
+    Ok(counted)
+}
+
+/// Vectorize corpus files of `config` then count frequency of each of its grams.
+/// It is the whole analysis of command line tool without printing or storing any result.
+/// 
+/// # Return
+/// Char map, vectorized corpus, and frequency table of each gram.
+/// Err if `stride` of `config` is 0, checkpoint cannot be loaded, corpus cannot be vectorized,
+/// user interrupt vectorization, or unique n-grams don't match the recount of `verify` of `config`.
+pub fn analyze(config: AnalyzeConfig) -> Result<AnalysisResult, AnalyzeError> {
+    // window start never advance with 0 stride
+    if config.stride == 0 {
+        return Err(AnalyzeError::Config("Stride must be at least 1".to_owned()))
+    }
+    let timer = Instant::now();
+    let map = RwLock::new(HashMap::<char, Id>::new());
+    let init = RwLock::new(1 as Id);
+    let checkpoint = match &config.checkpoint {
+        // standard input cannot be read again so it cannot be resumed
+        Some(_) if config.corpuses.iter().any(|f| f == Path::new("-")) => {
+            return Err(AnalyzeError::Checkpoint("Checkpoint cannot be used with standard input".to_owned()))
+        },
+        Some(path) => {
+            let checkpoint = Checkpoint::load(path, &init, &map).map_err(AnalyzeError::Checkpoint)?;
+            info!("Resume {} vectorized corpus files from checkpoint {}", checkpoint.len(), path.display());
+            Some(checkpoint)
+        },
+        None => None
+    };
+    let control = VectorizeControl {progress: &config.progress, interrupted: &config.interrupted};
+    let vectorized = vectorize(config.read, &config.vectorize, &config.corpuses, &init, &map, control, checkpoint.as_ref());
+    config.progress.finish_and_clear();
+    let mut vectorized = vectorized.map_err(AnalyzeError::Vectorize)?;
+    if config.interrupted.load(Ordering::SeqCst) {
+        return Err(AnalyzeError::Interrupted)
+    }
+    let parse_time = timer.elapsed();
+    let dropped_sentences = if config.drop_repeated_sentences { drop_repeated_sentences(&mut vectorized) } else { vec![] };
+
+    let window = WindowOption {
+        stride: config.stride,
+        sentence_lens: config.boundaries.segment_lens(&vectorized.sentence_lens, &vectorized.document_lens)
+    };
+    // progress is created before vectorization so counting is timed from here
+    config.count_progress.reset_elapsed();
+    config.count_progress.enable_steady_tick(Duration::from_millis(100));
+    let ngrams = if config.with_tags {
+        count_grams(&config, &vectorized.tagged, &window, &vectorized.document_lens)?.into_iter().map(|(c, times)| {
+            (ngram_entropy(&c.counts), NgramTable::Tagged(c), times)
+        }).collect::<Vec<_>>()
+    } else {
+        let vecs: Vec<Id> = vectorized.tagged.iter().map(|(v, _)| *v).collect();
+        count_grams(&config, &vecs, &window, &vectorized.document_lens)?.into_iter().map(|(c, times)| {
+            (ngram_entropy(&c.counts), NgramTable::Chars(c), times)
+        }).collect()
+    };
+    config.count_progress.finish_and_clear();
+    let ngrams = config.grams.iter().zip(ngrams).map(|(gram, (entropy, table, count_times))| {
+        GramAnalysis {gram: *gram, table, entropy, count_times}
+    }).collect();
+
+    Ok(AnalysisResult {
+        char_map: map.into_inner().unwrap(),
+        next_id: init.into_inner().unwrap(),
+        vectorized,
+        dropped_sentences,
+        ngrams,
+        parse_time
+    })
+}
+
+/// Outcome of a run that doesn't fully succeed. Each outcome has a distinct process exit code.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RunError {
+    /// Run is aborted. Exit code is 1.
+    Fatal(String),
+    /// Number of corpus files that are skipped since they cannot be opened or parsed. Exit code is 2.
+    SkippedFiles(usize),
+    /// No n-gram is produced from the corpus. Exit code is 3.
+    NoNgrams,
+    /// Output file cannot be written, e.g. disk is full. Exit code is 2.
+    Io(String),
+    /// Run is interrupted by user. Exit code is 130, the conventional code of process interrupted by Ctrl-C.
+    Interrupted
+}
+
+impl RunError {
+    /// Process exit code of this outcome. Successful run exit with 0.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            RunError::Fatal(_) => 1,
+            RunError::SkippedFiles(_) | RunError::Io(_) => 2,
+            RunError::NoNgrams => 3,
+            RunError::Interrupted => 130
+        }
+    }
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RunError::Fatal(err) | RunError::Io(err) => write!(f, "{}", err),
+            RunError::SkippedFiles(n) => write!(f, "{} corpus files are skipped since they cannot be opened or parsed", n),
+            RunError::NoNgrams => write!(f, "No n-gram is produced from the corpus"),
+            RunError::Interrupted => write!(f, "Analysis is interrupted. Only completed output is stored")
+        }
+    }
+}
+
+/// Determine outcome of a completed run. Producing no n-gram take precedence over skipped files.
+/// Producing no n-gram is not an error if every gram is longer than every sentence since it is
+/// warned by [gram_exceeds_sentences](fn.gram_exceeds_sentences.html).
+/// 
+/// # Parameter
+/// - `corpuses` - Number of corpus files to be vectorized.
+/// - `stats` - Statistic of each corpus file that is vectorized.
+/// - `ngrams` - Summary of analysis of each gram.
+pub fn run_outcome(corpuses: usize, stats: &[FileStats], ngrams: &[NgramSummary]) -> Result<(), RunError> {
+    if ngrams.iter().all(|n| n.unique == 0) && ngrams.iter().any(|n| n.exceeds_sentences.is_none()) {
+        Err(RunError::NoNgrams)
+    } else if stats.len() < corpuses {
+        Err(RunError::SkippedFiles(corpuses - stats.len()))
+    } else {
+        Ok(())
+    }
+}
+
+/// Time spent on each stage of the whole run in millisecond as pair of stage name and time.
+/// Time spent reading and vectorizing is summed over every corpus file and time spent
+/// counting and writing is summed over every gram.
+/// 
+/// # Parameter
+/// - `expand_time` - Time spent expanding corpus glob patterns.
+/// - `stats` - Statistic of each corpus file.
+/// - `ngrams` - Summary of analysis of each gram.
+pub fn stage_times(expand_time: Duration, stats: &[FileStats], ngrams: &[NgramSummary]) -> Vec<(&'static str, u64)> {
+    let vectorize_time: Duration = stats.iter().map(|s| s.vectorize_time).sum();
+    let read_time = stats.iter().map(|s| s.parse_time).sum::<Duration>().checked_sub(vectorize_time).unwrap_or_default();
+    let count_time = |field: fn(&CountTimes) -> Duration| ngrams.iter().map(|n| field(&n.count_times)).sum::<Duration>();
+    vec![
+        ("expand", expand_time),
+        ("read", read_time),
+        ("vectorize", vectorize_time),
+        ("flatten", count_time(|t| t.flatten)),
+        ("sort", count_time(|t| t.sort)),
+        ("group", count_time(|t| t.group)),
+        ("write", ngrams.iter().map(|n| n.write_time).sum())
+    ].into_iter().map(|(stage, time)| (stage, time.as_millis() as u64)).collect()
+}
+
+/// Log time spent on each stage as returned by [stage_times](fn.stage_times.html) at debug level.
+pub fn print_stage_times(stages: &[(&str, u64)]) {
+    debug!("{:>10} {:>10}", "stage", "ms");
+    for (stage, ms) in stages {
+        debug!("{:>10} {:>10}", stage, ms);
+    }
+}
+
+/// Settings of a run that are recorded in report by [write_report](fn.write_report.html).
+#[derive(Clone, Copy, Debug)]
+pub struct RunSettings {
+    /// Sample of documents that is vectorized, if documents are sampled.
+    pub sample: Option<Sample>,
+    /// Boundaries that n-gram windows don't cross.
+    pub boundaries: Boundaries
+}
+
+/// Write summary of the whole run as a JSON object into `path`. It is written atomically
+/// so an interrupted run never leave a partial report behind.
+/// 
+/// # Parameter
+/// - `stats` - Statistic of each corpus file.
+/// - `unique_chars` - Number of distinct vectorized characters.
+/// - `parse_time` - Time spent reading and vectorizing corpus.
+/// - `stages` - Time spent on each stage in millisecond. See [stage_times](fn.stage_times.html).
+/// - `settings` - Settings of the run that affect what is counted.
+/// - `ngrams` - Summary of analysis of each gram.
+pub fn write_report(path: &Path, stats: &[FileStats], unique_chars: usize, parse_time: Duration, stages: &[(&str, u64)], settings: &RunSettings, ngrams: &[NgramSummary]) -> std::io::Result<()> {
+    let total = |field: fn(&FileStats) -> usize| stats.iter().map(field).sum::<usize>();
+    let stages: serde_json::Map<String, serde_json::Value> = stages.iter().map(|(stage, ms)| (format!("{}_ms", stage), serde_json::json!(ms))).collect();
+    let report = serde_json::json!({
+        "files": stats.len(),
+        "documents": total(|s| s.documents),
+        "sentences": total(|s| s.sentences),
+        "words": total(|s| s.words),
+        "characters": total(|s| s.chars),
+        "unique_characters": unique_chars,
+        "parse_ms": parse_time.as_millis() as u64,
+        "analysis_ms": ngrams.iter().map(|n| n.count_times.total()).sum::<Duration>().as_millis() as u64,
+        "stages": stages,
+        "boundaries": settings.boundaries.to_string(),
+        "sample": settings.sample.map(|s| serde_json::json!({"fraction": s.fraction, "seed": s.seed})),
+        "ngrams": ngrams.iter().map(|n| serde_json::json!({
+            "gram": n.gram,
+            "unique": n.unique,
+            "windows": n.windows,
+            "oov_windows": n.oov_windows,
+            "entropy": n.entropy,
+            "analysis_ms": n.count_times.total().as_millis() as u64,
+            "flatten_ms": n.count_times.flatten.as_millis() as u64,
+            "sort_ms": n.count_times.sort.as_millis() as u64,
+            "group_ms": n.count_times.group.as_millis() as u64,
+            "write_ms": n.write_time.as_millis() as u64,
+            "perplexity": n.perplexity,
+            "gram_exceeds_longest_sentence": n.exceeds_sentences
+        })).collect::<Vec<serde_json::Value>>()
+    });
+    write_atomically(path, |file| {
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, &report)?;
+        writeln!(writer)?;
+        writer.flush()
+    })
+}
+
+/// Append `suffix` to file name of `path` right before its extension.
+/// For example, `out.csv` with suffix `intra` become `out.intra.csv`.
+pub fn suffixed_path(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let file_name = match path.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, suffix, ext.to_string_lossy()),
+        None => format!("{}.{}", stem, suffix)
+    };
+
+    path.with_file_name(file_name)
+}
+
+/// Run n-gram analysis on `raw` separately for intra-word and inter-word n-gram and report
+/// the result to `log`. The frequency table of each are written next to `out_path` of `option` with
+/// `intra_word` and `inter_word` suffix.
+/// See [count_ngrams_by_word_boundary](fn.count_ngrams_by_word_boundary.html) for the partition rule.
+/// 
+/// # Return
+/// Err if any frequency table cannot be written.
+pub fn analyze_boundary_split<T: Token>(gram: u8, raw: &[T], labels: &[Tag], window: &WindowOption, option: &ReportOption, log: &mut Vec<ReportLine>) -> std::io::Result<()> {
+    let timer = Instant::now();
+    let (intra, inter) = count_ngrams_by_word_boundary(gram, raw, labels, window);
+    log.push(ReportLine::Log(Level::Debug, format!("Total word boundary analysis time is {}s", timer.elapsed().as_secs())));
+
+    for (name, counts) in [("intra_word", intra), ("inter_word", inter)].iter() {
+        let total: usize = counts.iter().map(|(_, c)| c).sum();
+        log.push(ReportLine::Result(format!("Total {} unique {} {}-gram out of {} {}-gram", counts.len(), name, gram, total, gram)));
+        let counts = filter_min_count(counts, option.min_count);
+        let path = suffixed_path(&option.out_path, name);
+        write_atomically(&path, |out_file| {
+            write_ngram_table(out_file, &counts, option.format, option.delimiter, &option.rev_map, option.with_chars, ExtraColumns::default())
+        }).map_err(|err| write_error("word boundary analysis result", &path, err))?;
+        log.push(ReportLine::Log(Level::Info, format!("Store {} {}-gram to {}", name, gram, path.display())));
+    }
+
+    Ok(())
+}
+
+/// Run n-gram analysis on `raw` separately for n-gram at start, middle, and end of sentence
+/// and report the result to `log`. The frequency table of each are written next to `out_path`
+/// of `option` with `start`, `middle`, and `end` suffix.
+/// See [positional_window_starts](fn.positional_window_starts.html) for the bucketing rule.
+/// 
+/// # Return
+/// Err if any frequency table cannot be written.
+pub fn analyze_positional<T: Token>(gram: u8, raw: &[T], sentence_lens: &[usize], stride: usize, option: &ReportOption, log: &mut Vec<ReportLine>) -> std::io::Result<()> {
+    let timer = Instant::now();
+    let (start, middle, end) = count_ngrams_by_position(gram, raw, sentence_lens, stride);
+    log.push(ReportLine::Log(Level::Debug, format!("Total positional analysis time is {}s", timer.elapsed().as_secs())));
+
+    for (name, counts) in [("start", start), ("middle", middle), ("end", end)].iter() {
+        let total: usize = counts.iter().map(|(_, c)| c).sum();
+        log.push(ReportLine::Result(format!("Total {} unique sentence {} {}-gram out of {} {}-gram", counts.len(), name, gram, total, gram)));
+        let counts = filter_min_count(counts, option.min_count);
+        let path = suffixed_path(&option.out_path, name);
+        write_atomically(&path, |out_file| {
+            write_ngram_table(out_file, &counts, option.format, option.delimiter, &option.rev_map, option.with_chars, ExtraColumns::default())
+        }).map_err(|err| write_error("positional analysis result", &path, err))?;
+        log.push(ReportLine::Log(Level::Info, format!("Store sentence {} {}-gram to {}", name, gram, path.display())));
+    }
+
+    Ok(())
+}
+
+/// Name of each corpus file in `stats` to be used in name of its per-file output.
+/// It is file name without extension, or `stdin` for standard input.
+/// A name that is already used by previous file is suffixed by index of the file.
+pub fn per_file_names(stats: &[FileStats]) -> Vec<String> {
+    let mut used = std::collections::HashSet::new();
+    stats.iter().enumerate().map(|(i, stat)| {
+        let name = match stat.path.to_str() {
+            Some("-") => "stdin".to_owned(),
+            _ => stat.path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default()
+        };
+        let name = if used.contains(&name) { format!("{}_{}", name, i) } else { name };
+        used.insert(name.clone());
+        name
+    }).collect()
+}
+
+/// Run n-gram analysis on vectorized chars of each corpus file in `raw` separately.
+/// Frequency table of each file is stored next to `out_path` of `option` with name
+/// of the file as suffix. See [per_file_names](fn.per_file_names.html).
+/// Coverage table is only printed. Since the char map is shared, vectorized chars
+/// are comparable across files.
+/// 
+/// # Parameter
+/// - `gram` - Number of gram.
+/// - `raw` - Vectorized corpus of every file laid out in the same order as `stats`.
+/// - `window` - Options that control where windows of the whole `raw` start.
+///   If it has `sentence_lens`, they are split along with `raw`.
+/// - `stats` - Statistic of each corpus file. Its `chars` and `sentences` tell where each file start.
+/// - `option` - Options that control how the result is reported.
+/// - `log` - Each line to be printed to stdout is appended to it.
+/// 
+/// # Return
+/// Summary of the analysis of each file in the same order as `stats`.
+/// Err if any output file cannot be written.
+pub fn analyze_per_file<T: Token>(gram: u8, raw: &[T], window: &WindowOption, stats: &[FileStats], option: &ReportOption, log: &mut Vec<ReportLine>) -> std::io::Result<Vec<NgramSummary>> {
+    let mut char_offset = 0;
+    let mut segment_offset = 0;
+    let mut document_offset = 0;
+    stats.iter().zip(per_file_names(stats)).map(|(stat, name)| {
+        log.push(ReportLine::Result(format!("Analyzing {}-gram of {}", gram, stat.path.display())));
+        let file_raw = &raw[char_offset..(char_offset + stat.chars)];
+        // Segments may be either sentences or documents so take as many segments as it cover chars of this file
+        let file_segments = window.sentence_lens.map(|lens| {
+            let mut covered = 0;
+            let count = lens[segment_offset..].iter().take_while(|len| {
+                let take = covered < stat.chars;
+                covered += **len;
+                take
+            }).count();
+            let file_lens = &lens[segment_offset..(segment_offset + count)];
+            segment_offset += count;
+            file_lens
+        });
+        let file_window = WindowOption {
+            stride: window.stride,
+            sentence_lens: file_segments
+        };
+        let mut file_option = option.clone();
+        file_option.document_lens = option.document_lens.as_ref().map(|lens| lens[document_offset..(document_offset + stat.documents)].to_vec());
+        char_offset += stat.chars;
+        document_offset += stat.documents;
+        file_option.out_path = suffixed_path(&option.out_path, &name);
+        file_option.copy_path = None;
+        file_option.coverage_path = None;
+        file_option.fof_path = None;
+
+        analyze_ngrams(gram, file_raw, &file_window, &file_option, log)
+    }).collect()
+}
+
+/// Find documents that has identical vectorized chars. Each document is hashed by FNV-1a
+/// of its vectorized chars then documents of the same hash are compared so hash collision
+/// never report a false duplicate. Empty documents are ignored.
+/// 
+/// # Parameter
+/// - `tagged` - Slice of vectorized char and tag pair as returned from [vectorize](fn.vectorize.html).
+/// - `document_lens` - Length of each document of `tagged`.
+/// - `document_indices` - Index of each document within its corpus file.
+/// - `stats` - Statistic of each corpus file in the same order as `tagged`.
+/// 
+/// # Return
+/// Groups of at least two identical documents. Each document is identified by path of its
+/// corpus file and its index within the file. Groups are sorted by their first document.
+pub fn duplicate_documents<'a>(tagged: &[(Id, Tag)], document_lens: &[usize], document_indices: &[usize], stats: &'a [FileStats]) -> Vec<Vec<(&'a Path, usize)>> {
+    let files = stats.iter().flat_map(|stat| std::iter::repeat_n(stat.path.as_path(), stat.documents));
+    let mut start = 0;
+    let documents: Vec<_> = document_lens.iter().zip(document_indices).zip(files).map(|((len, idx), path)| {
+        let doc = &tagged[start..(start + len)];
+        start += len;
+        (doc, (path, *idx))
+    }).filter(|(doc, _)| !doc.is_empty()).collect();
+    let hashes: Vec<u64> = documents.par_iter().map(|(doc, _)| {
+        doc.iter().flat_map(|(v, _)| v.to_le_bytes().to_vec()).fold(0xcbf2_9ce4_8422_2325u64, |h, b| (h ^ b as u64).wrapping_mul(0x100_0000_01b3))
+    }).collect();
+    let mut by_hash: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (i, hash) in hashes.into_iter().enumerate() {
+        by_hash.entry(hash).or_default().push(i);
+    }
+
+    let same_chars = |a: &[(Id, Tag)], b: &[(Id, Tag)]| a.len() == b.len() && a.iter().zip(b).all(|(x, y)| x.0 == y.0);
+    let mut groups: Vec<Vec<(&Path, usize)>> = by_hash.into_iter().filter(|(_, docs)| docs.len() > 1).flat_map(|(_, mut docs)| {
+        let mut groups = vec![];
+        while let Some(first) = docs.first().cloned() {
+            let (same, rest): (Vec<usize>, Vec<usize>) = docs.into_iter().partition(|d| same_chars(documents[*d].0, documents[first].0));
+            docs = rest;
+            groups.push(same);
+        }
+        groups
+    }).filter(|group| group.len() > 1).map(|group| group.into_iter().map(|d| documents[d].1).collect()).collect();
+    groups.sort_unstable();
+
+    groups
+}
+
+/// Find sentences that has identical vectorized chars, e.g. boilerplate header or disclaimer
+/// repeated across documents. Empty sentences are ignored.
+/// 
+/// # Parameter
+/// - `tagged` - Slice of vectorized char and tag pair as returned from [vectorize](fn.vectorize.html).
+/// - `sentence_lens` - Length of each sentence of `tagged`.
+/// 
+/// # Return
+/// Vectorized chars of each sentence that occur more than once and its number of occurrence.
+/// Sorted by number of occurrence in descending order then by vectorized chars.
+pub fn repeated_sentences(tagged: &[(Id, Tag)], sentence_lens: &[usize]) -> Vec<(Vec<Id>, usize)> {
+    let mut counts: HashMap<Vec<Id>, usize> = HashMap::new();
+    let mut start = 0;
+    for len in sentence_lens {
+        if *len > 0 {
+            *counts.entry(tagged[start..(start + len)].iter().map(|(v, _)| *v).collect()).or_default() += 1;
+        }
+        start += len;
+    }
+    let mut repeated: Vec<(Vec<Id>, usize)> = counts.into_iter().filter(|(_, count)| *count > 1).collect();
+    repeated.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    repeated
+}
+
+/// Remove every occurrence of repeated sentence from `vectorized` except the first one so
+/// repeated sentences don't inflate n-gram frequency. Length of document and number of
+/// chars and sentences of corpus file that contain removed sentence are reduced accordingly
+/// while word statistics still count every sentence.
+/// 
+/// # Return
+/// Repeated sentences found before they are removed.
+/// See [repeated_sentences](fn.repeated_sentences.html).
+pub fn drop_repeated_sentences(vectorized: &mut Vectorized) -> Vec<(Vec<Id>, usize)> {
+    let repeated = repeated_sentences(&vectorized.tagged, &vectorized.sentence_lens);
+    let ends = |lens: Vec<usize>| lens.into_iter().scan(0, |end, len| { *end += len; Some(*end) }).collect::<Vec<usize>>();
+    let document_ends = ends(vectorized.document_lens.clone());
+    let file_ends = ends(vectorized.file_stats.iter().map(|stat| stat.chars).collect());
+    let mut seen = std::collections::HashSet::new();
+    let mut tagged = Vec::with_capacity(vectorized.tagged.len());
+    let mut sentence_lens = Vec::with_capacity(vectorized.sentence_lens.len());
+    let mut start = 0;
+    for len in &vectorized.sentence_lens {
+        let sentence = &vectorized.tagged[start..(start + len)];
+        if *len == 0 || seen.insert(sentence.iter().map(|(v, _)| *v).collect::<Vec<Id>>()) {
+            tagged.extend_from_slice(sentence);
+            sentence_lens.push(*len);
+        } else {
+            vectorized.document_lens[document_ends.partition_point(|end| *end <= start)] -= len;
+            let stat = &mut vectorized.file_stats[file_ends.partition_point(|end| *end <= start)];
+            stat.chars -= len;
+            stat.sentences -= 1;
+        }
+        start += len;
+    }
+    vectorized.tagged = tagged;
+    vectorized.sentence_lens = sentence_lens;
+
+    repeated
+}
+
+/// Count how many time each vectorized char appear in `tagged`.
+/// 
+/// # Parameter
+/// - `tagged` - Slice of vectorized char and tag pair as returned from [vectorize](fn.vectorize.html).
+/// 
+/// # Return
+/// A HashMap that map vectorized char to number of occurrence.
+pub fn char_frequencies(tagged: &[(Id, Tag)]) -> HashMap<Id, usize> {
+    tagged.par_iter().fold(HashMap::new, |mut freq, (v, _)| {
+        *freq.entry(*v).or_insert(0) += 1;
+        freq
+    }).reduce(HashMap::new, |mut a, b| {
+        b.into_iter().for_each(|(v, count)| *a.entry(v).or_insert(0) += count);
+        a
+    })
+}
+
+/// Write character frequencies as CSV into `path`.
+/// Each row contains vectorized char, actual char, and count, ordered by vectorized char.
+/// All excluded characters, which is vectorized to 0, are in a single "excluded" row.
+pub fn write_char_frequencies(path: &Path, freqs: &HashMap<Id, usize>, map: &HashMap<char, Id>) -> csv::Result<()> {
+    let rev_map: HashMap<Id, char> = map.iter().map(|(c, v)| (*v, *c)).collect();
+    let mut ids: Vec<&Id> = freqs.keys().collect();
+    ids.sort_unstable();
+    let mut writer = csv::Writer::from_path(path)?;
+    writer.write_record(["id", "char", "count"])?;
+
+    for id in ids {
+        let ch = match rev_map.get(id) {
+            Some(c) if *id != 0 => c.to_string(),
+            _ => "excluded".to_owned()
+        };
+        writer.write_record(&[id.to_string(), ch, freqs[id].to_string()])?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Print statistic of each corpus file as a table.
+pub fn print_file_stats(stats: &[FileStats]) {
+    println!("{:>10} {:>10} {:>10} {:>12} {:>10}  file", "documents", "sentences", "words", "characters", "parse(ms)");
+    for stat in stats {
+        println!("{:>10} {:>10} {:>10} {:>12} {:>10}  {}", stat.documents, stat.sentences, stat.words, stat.chars, stat.parse_time.as_millis(), stat.path.display());
+    }
+}
+
+/// Write statistic of each corpus file as CSV into `path`.
+pub fn write_file_stats(path: &Path, stats: &[FileStats]) -> csv::Result<()> {
+    let mut writer = csv::Writer::from_path(path)?;
+    writer.write_record(["file", "documents", "sentences", "words", "characters", "parse_time_ms"])?;
+
+    for stat in stats {
+        writer.write_record(&[
+            stat.path.display().to_string(),
+            stat.documents.to_string(),
+            stat.sentences.to_string(),
+            stat.words.to_string(),
+            stat.chars.to_string(),
+            stat.parse_time.as_millis().to_string()
+        ])?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Count transition between every two consecutive vectorized chars in `vecs`.
+/// If `sentence_lens` is given, transition across sentence boundary is not counted.
+/// 
+/// # Return
+/// A sparse transition matrix. It map a pair of vectorized chars to number of time
+/// the second char immediately follow the first char.
+pub fn transition_matrix(vecs: &[Id], sentence_lens: Option<&[usize]>) -> HashMap<(Id, Id), usize> {
+    let window = WindowOption {stride: 1, sentence_lens};
+    window_starts(2, vecs.len(), &window).into_par_iter().fold(HashMap::new, |mut matrix, i| {
+        *matrix.entry((vecs[i], vecs[i + 1])).or_insert(0) += 1;
+        matrix
+    }).reduce(HashMap::new, |mut a, b| {
+        b.into_iter().for_each(|(pair, count)| *a.entry(pair).or_insert(0) += count);
+        a
+    })
+}
+
+/// Build suffix array of `seq` by prefix doubling.
+/// 
+/// # Return
+/// Start of every suffix of `seq` in lexicographic order of the suffix.
+fn suffix_array(seq: &[u64]) -> Vec<usize> {
+    let n = seq.len();
+    let mut sa: Vec<usize> = (0..n).collect();
+    let mut rank: Vec<u64> = seq.to_vec();
+    let mut next_rank = vec![0u64; n];
+    if n <= 1 {
+        return sa
+    }
+    let mut k = 1;
+    loop {
+        // suffix shorter than k sort before every suffix that it is a prefix of
+        let key = |i: usize| (rank[i], if i + k < n { rank[i + k] + 1 } else { 0 });
+        sa.par_sort_unstable_by_key(|i| key(*i));
+        next_rank[sa[0]] = 0;
+        for w in 1..n {
+            next_rank[sa[w]] = next_rank[sa[w - 1]] + if key(sa[w]) == key(sa[w - 1]) { 0 } else { 1 };
+        }
+        std::mem::swap(&mut rank, &mut next_rank);
+        if rank[sa[n - 1]] as usize == n - 1 {
+            break
+        }
+        k *= 2;
+    }
+
+    sa
+}
+
+/// Find the longest sequence of vectorized chars that occur at least twice in `vecs` using
+/// suffix array and longest common prefix of adjacent suffixes. Occurrences may overlap.
+/// If `sentence_lens` is given, repeated sequence must fully lie inside a sentence.
+/// 
+/// # Return
+/// Start of the first occurrence in `vecs` and length of the longest repeated sequence.
+/// None if no vectorized char repeat.
+pub fn longest_repeat(vecs: &[Id], sentence_lens: Option<&[usize]>) -> Option<(usize, usize)> {
+    // a unique sentinel after each sentence never match anything so no repeat cross it
+    let mut seq: Vec<u64> = Vec::with_capacity(vecs.len() + sentence_lens.map(|l| l.len()).unwrap_or(0));
+    let mut origin: Vec<usize> = Vec::with_capacity(seq.capacity());
+    match sentence_lens {
+        Some(lens) => {
+            let mut offset = 0;
+            for (sentence, l) in lens.iter().enumerate() {
+                seq.extend(vecs[offset..(offset + l)].iter().map(|v| *v as u64));
+                origin.extend(offset..(offset + l));
+                seq.push(Id::MAX as u64 + 1 + sentence as u64);
+                origin.push(offset + l);
+                offset += l;
+            }
+        },
+        None => {
+            seq.extend(vecs.iter().map(|v| *v as u64));
+            origin.extend(0..vecs.len());
+        }
+    }
+    let n = seq.len();
+    let sa = suffix_array(&seq);
+    let mut rank = vec![0; n];
+    for (r, i) in sa.iter().enumerate() {
+        rank[*i] = r;
+    }
+
+    // Kasai's algorithm, longest common prefix drop by at most 1 from a suffix to the next
+    let mut best: Option<(usize, usize)> = None;
+    let mut lcp = 0;
+    for i in 0..n {
+        if rank[i] == 0 {
+            lcp = 0;
+            continue
+        }
+        let j = sa[rank[i] - 1];
+        while i + lcp < n && j + lcp < n && seq[i + lcp] == seq[j + lcp] {
+            lcp += 1;
+        }
+        if lcp > best.map(|(_, l)| l).unwrap_or(0) {
+            best = Some((origin[i.min(j)], lcp));
+        }
+        lcp = lcp.saturating_sub(1);
+    }
+
+    best
+}
+
+/// Compute mean and median of word length histogram `word_lens`.
+/// Median of even number of words is the mean of two middle lengths.
+/// 
+/// # Return
+/// None if there is no word. Otherwise, tuple of mean and median.
+pub fn word_len_summary(word_lens: &HashMap<usize, usize>) -> Option<(f64, f64)> {
+    let total: usize = word_lens.values().sum();
+    if total == 0 {
+        return None
+    }
+    let mut lens: Vec<(&usize, &usize)> = word_lens.iter().collect();
+    lens.sort_unstable();
+    let mean = lens.iter().map(|(len, words)| **len * **words).sum::<usize>() as f64 / total as f64;
+    // length of word at given rank in sorted order
+    let nth = |rank: usize| {
+        let mut seen = 0;
+        lens.iter().find(|(_, words)| {
+            seen += **words;
+            seen > rank
+        }).map(|(len, _)| **len).unwrap()
+    };
+    let median = (nth((total - 1) / 2) + nth(total / 2)) as f64 / 2f64;
+
+    Some((mean, median))
+}
+
+/// Write word length histogram as CSV of `length` and `words` into `path`.
+/// Rows are ordered by `length`.
+pub fn write_word_lens(path: &Path, word_lens: &HashMap<usize, usize>) -> csv::Result<()> {
+    let mut lens: Vec<&usize> = word_lens.keys().collect();
+    lens.sort_unstable();
+    let mut writer = csv::Writer::from_path(path)?;
+    writer.write_record(["length", "words"])?;
+
+    for len in lens {
+        writer.write_record(&[len.to_string(), word_lens[len].to_string()])?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write char `map` as CSV of `id` and `char` into `path`. Rows are ordered by `id`.
+/// If `equiv_classes` is not empty, there is also `class` column. Every member of a class
+/// that is in `map` get its own row with vectorized char of the class.
+pub fn write_char_map(path: &Path, map: &HashMap<char, Id>, equiv_classes: &EquivClasses) -> csv::Result<()> {
+    let mut pairs: Vec<(Id, char)> = vec![];
+    for (c, v) in map {
+        match equiv_classes.class_of(*c) {
+            Some(class) => pairs.extend(class.chars.iter().map(|member| (*v, *member))),
+            None => pairs.push((*v, *c))
+        }
+    }
+    pairs.sort_unstable();
+    let mut writer = csv::Writer::from_path(path)?;
+    if equiv_classes.is_empty() {
+        writer.write_record(["id", "char"])?;
+    } else {
+        writer.write_record(["id", "char", "class"])?;
+    }
+
+    for (id, ch) in pairs {
+        if equiv_classes.is_empty() {
+            writer.write_record(&[id.to_string(), ch.to_string()])?;
+        } else {
+            let class = equiv_classes.class_of(ch).map(|class| class.name.as_str()).unwrap_or("");
+            writer.write_record(&[id.to_string(), ch.to_string(), class.to_owned()])?;
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Merge char map `b` into char map `a`. Every char of `a` keep its vectorized char.
+/// Char of `b` that is also in `a` take vectorized char from `a`. Other chars of `b`
+/// are assigned new vectorized chars after the largest one of `a`, in order of their
+/// vectorized char in `b`.
+///
+/// # Return
+/// A tuple of merged char map and a HashMap that translate vectorized char of `b`
+/// into vectorized char of merged char map.
+pub fn merge_maps(a: HashMap<char, Id>, b: HashMap<char, Id>) -> (HashMap<char, Id>, HashMap<Id, Id>) {
+    let mut next = a.values().max().map(|v| v + 1).unwrap_or(1);
+    let mut b_pairs: Vec<(Id, char)> = b.into_iter().map(|(c, v)| (v, c)).collect();
+    b_pairs.sort_unstable();
+    let mut merged = a;
+    let remap = b_pairs.into_iter().map(|(old, ch)| {
+        let new = *merged.entry(ch).or_insert_with(|| {
+            next += 1;
+            next - 1
+        });
+        (old, new)
+    }).collect();
+
+    (merged, remap)
+}
+
+/// Write transition matrix as CSV of `from`, `to`, and `count` triplet into `path`.
+/// Rows are ordered by `from` then `to`.
+pub fn write_transitions(path: &Path, matrix: &HashMap<(Id, Id), usize>) -> csv::Result<()> {
+    let mut pairs: Vec<&(Id, Id)> = matrix.keys().collect();
+    pairs.sort_unstable();
+    let mut writer = csv::Writer::from_path(path)?;
+    writer.write_record(["from", "to", "count"])?;
+
+    for pair in pairs {
+        writer.write_record(&[pair.0.to_string(), pair.1.to_string(), matrix[pair].to_string()])?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Minimal number of bytes, at least 1, that can hold every value up to `max`.
+pub fn min_bytes(max: u64) -> usize {
+    let bits = 64 - max.leading_zeros() as usize;
+    bits.div_ceil(8).max(1)
+}
+
+/// Write vectorized corpus into `path` in a compact binary format.
+/// 
+/// The layout, all integer are little-endian, is:
+/// - 4 bytes magic `BSTV`
+/// - 1 byte width of each vectorized char in bytes, `W`, from 1 to 4
+/// - 1 byte width of each tag in bytes, `T`, either 1 or 2
+/// - 8 bytes unsigned number of characters, `N`
+/// - `N` vectorized chars, each one is `W` bytes
+/// - `N` tags, each one is `T` bytes
+/// 
+/// `W` and `T` are the minimal widths that can hold the largest vectorized char and tag
+/// in `tagged`, so a corpus of 120 distinct chars take 1 byte per char regardless of `--id-width`.
+/// A reader should size its parse from the header rather than assume any width.
+/// 
+/// With numpy, it can be read by `np.fromfile(path, dtype=np.uint8, count=N, offset=14)` for
+/// vectorized chars when `W` is 1, `dtype='<u2'` when `W` is 2, and `dtype='<u4'` when `W` is 4.
+/// When `W` is 3, read `N * 3` bytes as `np.uint8`, reshape to `(N, 3)` and combine each row
+/// as `b0 | b1 << 8 | b2 << 16`. Tags can be read by `np.fromfile(path, dtype=np.uint8, count=N, offset=14 + N * W)`
+/// when `T` is 1 and `dtype='<u2'` when `T` is 2. See [read_vectors](fn.read_vectors.html).
+pub fn write_vectors(path: &Path, tagged: &[(Id, Tag)]) -> std::io::Result<()> {
+    let width = min_bytes(tagged.iter().map(|(v, _)| *v as u64).max().unwrap_or(0));
+    let tag_bytes = min_bytes(tagged.iter().map(|(_, l)| *l as u64).max().unwrap_or(0));
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(b"BSTV")?;
+    writer.write_all(&[width as u8, tag_bytes as u8])?;
+    writer.write_all(&(tagged.len() as u64).to_le_bytes())?;
+    for (v, _) in tagged {
+        writer.write_all(&v.to_le_bytes()[..width])?;
+    }
+    for (_, l) in tagged {
+        writer.write_all(&l.to_le_bytes()[..tag_bytes])?;
+    }
+    writer.flush()
+}
+
+/// Read vectorized corpus written by [write_vectors](fn.write_vectors.html) from `path`.
+/// 
+/// # Return
+/// Pair of vectorized char and tag. Err with `InvalidData` kind if `path` is not in the format,
+/// its widths exceed `Id` or `Tag`, or its length in header doesn't match size of the file.
+pub fn read_vectors(path: &Path) -> std::io::Result<Vec<(Id, Tag)>> {
+    let invalid = |msg: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path.display(), msg));
+    let file = File::open(path)?;
+    let file_len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+    let mut header = [0u8; 14];
+    reader.read_exact(&mut header)?;
+    if &header[..4] != b"BSTV" {
+        return Err(invalid("missing BSTV magic"))
+    }
+    let (width, tag_bytes) = (header[4] as usize, header[5] as usize);
+    if !(1..=std::mem::size_of::<Id>()).contains(&width) || !(1..=std::mem::size_of::<Tag>()).contains(&tag_bytes) {
+        return Err(invalid("unsupported width"))
+    }
+    let mut len = [0u8; 8];
+    len.copy_from_slice(&header[6..]);
+    let len = u64::from_le_bytes(len);
+    // length in header is checked against the file before anything is allocated for it
+    let body = len.checked_mul((width + tag_bytes) as u64);
+    if body != Some(file_len.saturating_sub(header.len() as u64)) {
+        return Err(invalid(&format!("header declare {} vectorized chars but file has {} bytes", len, file_len)))
+    }
+    let len = len as usize;
+
+    let mut vecs = vec![0u8; len * width];
+    reader.read_exact(&mut vecs)?;
+    let mut tags = vec![0u8; len * tag_bytes];
+    reader.read_exact(&mut tags)?;
+    let le = |bytes: &[u8]| bytes.iter().rev().fold(0u64, |n, b| (n << 8) | *b as u64);
+
+    Ok(vecs.chunks(width).zip(tags.chunks(tag_bytes)).map(|(v, l)| (le(v) as Id, le(l) as Tag)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Path of temporary file unique to this process and `name`.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("best_analysis_{}_{}", std::process::id(), name))
+    }
+
+    /// Write `content` into temporary file `name`.
+    fn write_temp(name: &str, content: &[u8]) -> PathBuf {
+        let path = temp_path(name);
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    /// JSON corpus of `docs` where each word is its characters and tag.
+    fn corpus_json(docs: &[Vec<Vec<(&str, Tag)>>]) -> String {
+        serde_json::json!(docs.iter().map(|doc| doc.iter().map(|sentence| sentence.iter().map(|(word, tag)| {
+            serde_json::json!([word.chars().map(|c| c.to_string()).collect::<Vec<String>>(), tag])
+        }).collect::<Vec<_>>()).collect::<Vec<_>>()).collect::<Vec<_>>()).to_string()
+    }
+
+    /// Write JSON corpus of `docs` into temporary file `name`.
+    fn write_corpus(name: &str, docs: &[Vec<Vec<(&str, Tag)>>]) -> PathBuf {
+        write_temp(name, corpus_json(docs).as_bytes())
+    }
+
+    fn read_option() -> ReadOption {
+        ReadOption {buf_size: 8192, format: InputFormat::Json, encoding: Encoding::Utf8, strict: true}
+    }
+
+    /// Default option of command line tool where only Thai characters are vectorized.
+    fn thai_option() -> VectorizeOption {
+        AnalyzeConfig::new(vec![], vec![]).vectorize
+    }
+
+    /// Vectorize `corpuses` with fresh char map.
+    fn vectorize_with(option: &VectorizeOption, corpuses: &[PathBuf]) -> Result<(Vectorized, HashMap<char, Id>), String> {
+        let init = RwLock::new(1);
+        let map = RwLock::new(HashMap::new());
+        let vectorized = vectorize(read_option(), option, corpuses, &init, &map, control(&ProgressBar::hidden(), &AtomicBool::new(false)), None)?;
+        Ok((vectorized, map.into_inner().unwrap()))
+    }
+
+    fn control<'a>(progress: &'a ProgressBar, interrupted: &'a AtomicBool) -> VectorizeControl<'a> {
+        VectorizeControl {progress, interrupted}
+    }
+
+    fn ids(tagged: &[(Id, Tag)]) -> Vec<Id> {
+        tagged.iter().map(|(v, _)| *v).collect()
+    }
+
+    /// Deterministic pseudo random corpus of `len` vectorized chars from 1 to `alphabet`.
+    fn seeded(len: usize, alphabet: Id, seed: u64) -> Vec<Id> {
+        let mut x = seed;
+        (0..len).map(|_| {
+            x = x.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+            (x >> 33) as Id % alphabet + 1
+        }).collect()
+    }
+
+    /// Sentence lengths, including empty and single char sentence, that sum to `len`.
+    fn seeded_lens(len: usize) -> Vec<usize> {
+        let mut lens = vec![];
+        let mut covered = 0;
+        for l in [7, 0, 1, 13, 3, 2, 21, 5].iter().cycle() {
+            if covered + l > len {
+                lens.push(len - covered);
+                break
+            }
+            lens.push(*l);
+            covered += l;
+        }
+        lens
+    }
+
+    fn file_stat(path: &str, documents: usize, sentences: usize, chars: usize) -> FileStats {
+        FileStats {
+            path: PathBuf::from(path),
+            documents,
+            sentences,
+            words: 0,
+            chars,
+            parse_time: Duration::default(),
+            vectorize_time: Duration::default()
+        }
+    }
+
+    fn summary(unique: usize, exceeds_sentences: Option<usize>) -> NgramSummary {
+        NgramSummary {
+            gram: 2,
+            unique,
+            windows: unique,
+            oov_windows: None,
+            entropy: 0f64,
+            count_times: CountTimes::default(),
+            write_time: Duration::default(),
+            buffer_bytes: 0,
+            perplexity: None,
+            exceeds_sentences
+        }
+    }
+
+    /// Option that write CSV n-gram frequency table to `out_path` without printing anything.
+    fn report_option(out_path: PathBuf) -> ReportOption {
+        ReportOption {
+            top: None,
+            oov_id: None,
+            max_ngrams: None,
+            min_count: 1,
+            coverage: vec![],
+            rev_map: HashMap::new(),
+            out_path,
+            copy_path: None,
+            coverage_path: None,
+            fof_path: None,
+            format: OutputFormat::Csv,
+            delimiter: b',',
+            with_chars: false,
+            document_lens: None,
+            shard_by_prefix: false,
+            smoothing: Smoothing::None,
+            append: false
+        }
+    }
+
+    /// N-gram and count of each row of CSV n-gram frequency table in `path`.
+    fn csv_counts(path: &Path) -> HashMap<String, usize> {
+        std::fs::read_to_string(path).unwrap().lines().skip(1).map(|line| {
+            let (ngram, count) = line.split_once(',').unwrap();
+            (ngram.to_owned(), count.parse::<usize>().unwrap())
+        }).collect()
+    }
+
+    #[test]
+    fn windows_stay_inside_sentence() {
+        let raw: Vec<Id> = vec![1, 2, 3, 1, 2];
+        let lens = [3, 2];
+        let window = WindowOption {stride: 1, sentence_lens: Some(&lens)};
+        assert_eq!(window_starts(2, raw.len(), &window), vec![0, 1, 3]);
+        assert_eq!(window_starts(2, raw.len(), &WindowOption {stride: 1, sentence_lens: None}), vec![0, 1, 2, 3]);
+        assert_eq!(count_ngrams(2, &raw, &window), vec![(vec![1, 2], 2), (vec![2, 3], 1)]);
+        assert_eq!(window_starts(4, raw.len(), &window), Vec::<usize>::new());
+        assert_eq!(Boundaries::Sentence.segment_lens(&[1], &[2]), Some(&[1][..]));
+        assert_eq!(Boundaries::Document.segment_lens(&[1], &[2]), Some(&[2][..]));
+    }
+
+    #[test]
+    fn documents_are_consumed_as_parsed() {
+        // the first document is consumed before the broken rest of corpus is seen
+        let mut docs = vec![];
+        let result = for_each_corpus_document("[[[[[\"ก\"],1]]], [[[[\"ข\"],1]]], oops".as_bytes(), InputFormat::Json, |doc| {
+            docs.push(doc);
+            Ok(())
+        });
+        assert!(result.is_err());
+        assert_eq!(docs.len(), 2);
+        let path = write_corpus("streaming_vectorize.json", &[vec![vec![("กข", 1)]], vec![vec![("ค", 2)]]]);
+        let (vectorized, _) = vectorize_with(&thai_option(), &[path]).unwrap();
+        assert_eq!(vectorized.tagged, vec![(1, 0), (2, 1), (3, 2)]);
+        assert_eq!(vectorized.document_lens, vec![2, 1]);
+    }
+
+    #[test]
+    fn char_frequencies_count_each_char() {
+        let tagged = [(1, 0), (2, 1), (1, 1), (0, 1)];
+        let freqs = char_frequencies(&tagged);
+        assert_eq!(freqs, [(0, 1), (1, 2), (2, 1)].iter().cloned().collect());
+        let path = temp_path("char_freq.csv");
+        let map = [('ก', 1), ('ข', 2)].iter().cloned().collect();
+        write_char_frequencies(&path, &freqs, &map).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "id,char,count\n0,excluded,1\n1,ก,2\n2,ข,1\n");
+    }
+
+    #[test]
+    fn entropy_of_uniform_distribution() {
+        let counts: NgramCounts<Id> = (1..=4).map(|v| (vec![v], 3)).collect();
+        assert!((ngram_entropy(&counts) - 2f64).abs() < 1e-12);
+        assert_eq!(ngram_entropy::<Id>(&[]), 0f64);
+        assert_eq!(ngram_entropy(&counts[..1]), 0f64);
+    }
+
+    #[test]
+    fn top_ngrams_order_tie_by_ngram() {
+        let counts: NgramCounts<Id> = vec![(vec![1], 2), (vec![2], 3), (vec![3], 2)];
+        let top: Vec<&(Vec<Id>, usize)> = top_ngrams(&counts, 2);
+        assert_eq!(top, vec![&(vec![2], 3), &(vec![1], 2)]);
+        assert_eq!(top_ngrams(&counts, 10).len(), 3);
+    }
+
+    #[test]
+    fn vectors_round_trip() {
+        let tagged: Vec<(Id, Tag)> = vec![(1, 0), (2, 1), (3, 0), (1, 2)];
+        let path = temp_path("vectors.bin");
+        write_vectors(&path, &tagged).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(&bytes[..4], b"BSTV");
+        assert_eq!(&bytes[6..14], &(tagged.len() as u64).to_le_bytes());
+        assert_eq!(read_vectors(&path).unwrap(), tagged);
+        assert_eq!(read_vectors(&write_temp("vectors_bad.bin", b"NOPE0000000000")).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn parse_configurable_unicode_ranges() {
+        assert_eq!(parse_unicode_ranges("0E01..0E7F, 0x41..0x5A").unwrap(), vec![0x0E01..=0x0E7F, 0x41..=0x5A]);
+        assert!(parse_unicode_ranges("5A..41").is_err());
+        assert!(parse_unicode_ranges("zz..41").is_err());
+        assert!(parse_unicode_ranges("41").is_err());
+        let path = write_corpus("latin_range.json", &[vec![vec![("aกb", 1)]]]);
+        let mut option = thai_option();
+        option.unicode_ranges = parse_unicode_ranges("61..62").unwrap();
+        option.oov_mode = OovMode::Drop;
+        let (vectorized, map) = vectorize_with(&option, &[path]).unwrap();
+        assert_eq!(vectorized.tagged, vec![(1, 0), (2, 1)]);
+        assert_eq!(map, [('a', 1), ('b', 2)].iter().cloned().collect());
+    }
+
+    #[test]
+    fn tagged_ngram_differ_by_tag() {
+        let raw: Vec<(Id, Tag)> = vec![(1, 0), (2, 1), (1, 0), (2, 0)];
+        let counts = count_ngrams(2, &raw, &WindowOption {stride: 1, sentence_lens: None});
+        assert_eq!(counts, vec![(vec![(1, 0), (2, 0)], 1), (vec![(1, 0), (2, 1)], 1), (vec![(2, 1), (1, 0)], 1)]);
+        let vecs = ids(&raw);
+        assert_eq!(count_ngrams(2, &vecs, &WindowOption {stride: 1, sentence_lens: None}), vec![(vec![1, 2], 2), (vec![2, 1], 1)]);
+    }
+
+    #[test]
+    fn empty_match_is_error() {
+        assert_eq!(expand_corpuses(&[]), Err("No corpus files matched: ".to_owned()));
+        let missing = temp_path("missing_dir").join("*.json");
+        let missing = missing.to_str().unwrap();
+        assert_eq!(expand_corpuses(&[missing]), Err(format!("No corpus files matched: {}", missing)));
+        assert!(matches!(expand_corpuses(&["a[b"]), Err(err) if err.contains("a[b")));
+        let result = analyze(AnalyzeConfig::new(vec![2], vec![])).unwrap();
+        assert!(result.vectorized.tagged.is_empty());
+    }
+
+    #[test]
+    fn file_stats_of_each_file() {
+        let path = write_corpus("file_stats.json", &[
+            vec![vec![("กข", 1), ("ค", 1)], vec![("ง", 1)]],
+            vec![vec![("กก", 1)]]
+        ]);
+        let (vectorized, _) = vectorize_with(&thai_option(), std::slice::from_ref(&path)).unwrap();
+        let stat = &vectorized.file_stats[0];
+        assert_eq!(stat.path, path);
+        assert_eq!((stat.documents, stat.sentences, stat.words, stat.chars), (2, 3, 4, 6));
+        assert_eq!(vectorized.sentence_lens, vec![3, 1, 2]);
+        assert_eq!(vectorized.document_lens, vec![4, 2]);
+        assert_eq!(vectorized.document_indices, vec![0, 1]);
+    }
+
+    #[test]
+    fn oov_mode_of_excluded_char() {
+        let path = write_corpus("oov_mode.json", &[vec![vec![("กaข", 1)]]]);
+        let mut option = thai_option();
+        option.oov_mode = OovMode::Drop;
+        assert_eq!(vectorize_with(&option, std::slice::from_ref(&path)).unwrap().0.tagged, vec![(1, 0), (2, 1)]);
+        option.oov_mode = OovMode::Token;
+        option.id_width = IdWidth::U16;
+        assert_eq!(vectorize_with(&option, std::slice::from_ref(&path)).unwrap().0.tagged, vec![(1, 0), (65_535, 0), (2, 1)]);
+        option.oov_mode = OovMode::KeepPosition;
+        assert_eq!(vectorize_with(&option, &[path]).unwrap().0.tagged, vec![(1, 0), (0, 0), (2, 1)]);
+        assert_eq!(OovMode::Drop.oov_id(IdWidth::U8), None);
+        assert_eq!(OovMode::Token.oov_id(IdWidth::U8), Some(255));
+        assert_eq!(OovMode::KeepPosition.oov_id(IdWidth::U32), Some(0));
+    }
+
+    #[test]
+    fn validate_report_location_of_violation() {
+        let path = write_temp("validate.json", "[[[[[\"ก\"],1],[[],1]],[]],[]]".as_bytes());
+        let violations = validate_corpuses(read_option(), std::slice::from_ref(&path));
+        assert_eq!(violations, vec![
+            format!("{}: document 0 sentence 0 word 1 has no character", path.display()),
+            format!("{}: document 0 sentence 1 has no word", path.display()),
+            format!("{}: document 1 has no sentence", path.display())
+        ]);
+        let broken = write_temp("validate_broken.json", b"[[[");
+        assert_eq!(validate_corpuses(read_option(), &[broken]).len(), 1);
+    }
+
+    #[test]
+    fn json_table_has_every_field() {
+        let counts: NgramCounts<(Id, Tag)> = vec![(vec![(1, 0), (2, 1)], 3)];
+        let rev_map = [(1, 'ก'), (2, 'ข')].iter().cloned().collect();
+        let mut out = vec![];
+        let columns = ExtraColumns {document_frequency: Some(&[1]), probability: Some(&[0.5])};
+        write_ngram_table(&mut out, &counts, OutputFormat::Json, b',', &rev_map, false, columns).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json, serde_json::json!([{"ngram": [1, 2], "tags": [0, 1], "chars": "กข", "count": 3, "document_frequency": 1, "probability": 0.5}]));
+    }
+
+    #[test]
+    fn normalization_before_vectorize() {
+        let path = write_corpus("normalization.json", &[vec![vec![("e\u{301}", 1)]]]);
+        let mut option = thai_option();
+        option.unicode_ranges = vec![0..=0x10FFFF];
+        assert_eq!(vectorize_with(&option, std::slice::from_ref(&path)).unwrap().0.tagged.len(), 2);
+        option.normalization = Normalization::Nfc;
+        let (vectorized, map) = vectorize_with(&option, &[path]).unwrap();
+        assert_eq!(vectorized.tagged, vec![(1, 1)]);
+        assert_eq!(map[&'\u{e9}'], 1);
+        let composed = write_corpus("normalization_nfd.json", &[vec![vec![("\u{e9}", 1)]]]);
+        option.normalization = Normalization::Nfd;
+        assert_eq!(vectorize_with(&option, &[composed]).unwrap().0.tagged.len(), 2);
+    }
+
+    #[test]
+    fn id_width_exhaustion_is_error() {
+        let chars = |n: u32| (0..n).map(|i| std::char::from_u32(0x4E00 + i).unwrap()).collect::<String>();
+        let mut option = thai_option();
+        option.unicode_ranges = vec![0x4E00..=0x9FFF];
+        option.id_width = IdWidth::U8;
+        // 255 is reserved for oov token and 0 for excluded char
+        let fit = chars(254);
+        let path = write_corpus("id_width_fit.json", &[vec![vec![(fit.as_str(), 1)]]]);
+        assert_eq!(vectorize_with(&option, &[path]).unwrap().1.len(), 254);
+        let exceed = chars(255);
+        let path = write_corpus("id_width_exceed.json", &[vec![vec![(exceed.as_str(), 1)]]]);
+        let err = vectorize_with(&option, &[path]).err().unwrap();
+        assert!(err.contains("exceed u8 id width"), "{}", err);
+    }
+
+    #[test]
+    fn ngram_split_by_word_boundary() {
+        let raw: Vec<Id> = vec![1, 2, 3, 4];
+        let labels = [0, 5, 0, 5];
+        let (intra, inter) = count_ngrams_by_word_boundary(2, &raw, &labels, &WindowOption {stride: 1, sentence_lens: None});
+        assert_eq!(intra, vec![(vec![1, 2], 1), (vec![3, 4], 1)]);
+        assert_eq!(inter, vec![(vec![2, 3], 1)]);
+        // last char of window may end a word without crossing it
+        let (intra, inter) = count_ngrams_by_word_boundary(1, &raw, &labels, &WindowOption {stride: 1, sentence_lens: None});
+        assert_eq!((intra.len(), inter.len()), (4, 0));
+    }
+
+    #[test]
+    fn coverage_count_most_frequent_ngrams() {
+        let counts: NgramCounts<Id> = vec![(vec![1], 2), (vec![2], 5), (vec![3], 3)];
+        assert_eq!(coverage(&counts, &[0.5, 0.8, 0.81, 1.0]), vec![(0.5, 1), (0.8, 2), (0.81, 3), (1.0, 3)]);
+        assert_eq!(parse_coverage_thresholds("0.5, 1").unwrap(), vec![0.5, 1.0]);
+        assert!(parse_coverage_thresholds("0").is_err());
+        assert!(parse_coverage_thresholds("1.5").is_err());
+    }
+
+    #[test]
+    fn char_list_line_of_range() {
+        assert_eq!(parse_char_list_line("a-c").unwrap(), vec!['a', 'b', 'c']);
+        assert_eq!(parse_char_list_line("0030-0032").unwrap(), vec!['0', '1', '2']);
+        assert_eq!(parse_char_list_line("xyz").unwrap(), vec!['x']);
+        assert_eq!(parse_char_list_line("-").unwrap(), vec!['-']);
+        assert!(parse_char_list_line("c-a").is_err());
+    }
+
+    #[test]
+    fn stride_restart_at_each_sentence() {
+        let lens = [5, 3];
+        assert_eq!(window_starts(2, 8, &WindowOption {stride: 2, sentence_lens: Some(&lens)}), vec![0, 2, 5]);
+        assert_eq!(window_starts(2, 8, &WindowOption {stride: 2, sentence_lens: None}), vec![0, 2, 4, 6]);
+        // stride equal to gram tile the corpus without overlapping
+        assert_eq!(window_starts(3, 7, &WindowOption {stride: 3, sentence_lens: None}), vec![0, 3]);
+        let raw: Vec<Id> = vec![1, 2, 1, 2, 1, 2];
+        assert_eq!(count_ngrams(2, &raw, &WindowOption {stride: 2, sentence_lens: None}), vec![(vec![1, 2], 3)]);
+        assert_eq!(count_ngrams(2, &raw, &WindowOption {stride: 1, sentence_lens: None}), vec![(vec![1, 2], 3), (vec![2, 1], 2)]);
+    }
+
+    #[test]
+    fn transitions_respect_sentence() {
+        let vecs = [1, 2, 1, 2];
+        assert_eq!(transition_matrix(&vecs, Some(&[2, 2])), [((1, 2), 2)].iter().cloned().collect());
+        assert_eq!(transition_matrix(&vecs, None), [((1, 2), 2), ((2, 1), 1)].iter().cloned().collect());
+    }
+
+    #[test]
+    fn overlapping_patterns_match_file_once() {
+        let dir = temp_path("overlap");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("a.json");
+        std::fs::write(&path, corpus_json(&[vec![vec![("กข", 1)]]])).unwrap();
+        let all = dir.join("*.json");
+        let corpuses = expand_corpuses(&[all.to_str().unwrap(), path.to_str().unwrap()]).unwrap();
+        assert_eq!(corpuses, vec![path.clone()]);
+        let (vectorized, _) = vectorize_with(&thai_option(), &corpuses).unwrap();
+        assert_eq!(vectorized.file_stats.len(), 1);
+        assert_eq!(vectorized.tagged.len(), 2);
+    }
+
+    #[test]
+    fn memory_estimates() {
+        assert_eq!(vec_bytes(&[0 as Id; 10]), 10 * std::mem::size_of::<Id>());
+        assert_eq!(vec_bytes(&[(0 as Id, 0 as Tag); 3]), 3 * std::mem::size_of::<(Id, Tag)>());
+        assert_eq!(ngram_buffer_bytes(3), 3 * std::mem::size_of::<usize>());
+        assert_eq!(char_map_bytes(2), 16);
+        if Path::new("/proc/self/status").exists() {
+            assert!(peak_rss().unwrap() > 0);
+        }
+    }
+
+    #[test]
+    fn slice_sort_match_materialized_windows() {
+        let raw = seeded(800, 6, 23);
+        let lens = seeded_lens(raw.len());
+        for gram in 1..=4u8 {
+            let window = WindowOption {stride: 1, sentence_lens: Some(&lens)};
+            let starts = window_starts(gram as usize, raw.len(), &window);
+            // the way windows were counted before, by copying each window into its own Vec
+            let mut materialized: Vec<Vec<Id>> = starts.iter().map(|i| raw[*i..(*i + gram as usize)].to_vec()).collect();
+            materialized.sort();
+            let mut expected: NgramCounts<Id> = vec![];
+            for ngram in materialized.iter() {
+                match expected.last_mut() {
+                    Some((last, count)) if last == ngram => *count += 1,
+                    _ => expected.push((ngram.clone(), 1))
+                }
+            }
+
+            let sorted = sorted_windows(gram, &raw, starts);
+            assert_eq!(sorted.iter().map(|i| raw[*i..(*i + gram as usize)].to_vec()).collect::<Vec<Vec<Id>>>(), materialized);
+            assert_eq!(count_ngrams(gram, &raw, &window), expected);
+        }
+    }
+
+    #[test]
+    fn tag_filter_drop_whole_word() {
+        let path = write_corpus("tag_filter.json", &[vec![vec![("ก", 1), ("ขค", 2), ("ง", 1)]]]);
+        let mut option = thai_option();
+        option.tag_filter = TagFilter::Only(parse_tags("2").unwrap());
+        let (vectorized, _) = vectorize_with(&option, std::slice::from_ref(&path)).unwrap();
+        assert_eq!(vectorized.tagged, vec![(1, 0), (2, 2)]);
+        assert_eq!(vectorized.sentence_lens, vec![2]);
+        option.tag_filter = TagFilter::Exclude(parse_tags("2, 3").unwrap());
+        assert_eq!(vectorize_with(&option, &[path]).unwrap().0.tagged, vec![(1, 1), (2, 1)]);
+        assert!(parse_tags("1,x").is_err());
+    }
+
+    #[test]
+    fn parse_best_text_tags() {
+        let text = "ก|<NE>ขค</NE>|ง|  \n\n<AB>จ|ฉ</AB>|\n";
+        let doc = parse_best_text(text.as_bytes()).unwrap();
+        assert_eq!(doc, vec![
+            vec![("ก".to_owned(), 1), ("ขค".to_owned(), 2), ("ง".to_owned(), 1)],
+            vec![("จ".to_owned(), 3), ("ฉ".to_owned(), 3)]
+        ]);
+        assert!(parse_best_text("<XX>ก</XX>|".as_bytes()).unwrap_err().contains("unknown tag"));
+        assert!(parse_best_text("<NE>ก|".as_bytes()).unwrap_err().contains("never closed"));
+        assert!(parse_best_text("ก</NE>|".as_bytes()).unwrap_err().contains("unexpected closing tag"));
+        let mut docs = 0;
+        for_each_corpus_document(text.as_bytes(), InputFormat::BestText, |doc| {
+            docs += 1;
+            assert_eq!(doc.len(), 2);
+            Ok(())
+        }).unwrap();
+        assert_eq!(docs, 1);
+    }
+
+    #[test]
+    fn perplexity_with_add_one_smoothing() {
+        let window = WindowOption {stride: 1, sentence_lens: None};
+        let train: Vec<Id> = vec![1, 2];
+        let seen = perplexity(2, &train, &window, &[1, 2], &window, 2).unwrap();
+        assert!((seen - 1.5).abs() < 1e-12);
+        let unseen = perplexity(2, &train, &window, &[2, 1], &window, 2).unwrap();
+        assert!((unseen - 2f64).abs() < 1e-12);
+        assert_eq!(perplexity(2, &train, &window, &[1], &window, 2), None);
+    }
+
+    #[test]
+    fn parallel_csv_match_serial_csv() {
+        let counts: NgramCounts<Id> = (0..40_000).map(|i| (vec![i, i % 7], i as usize % 13 + 1)).collect();
+        let df: Vec<usize> = counts.iter().map(|(_, c)| c / 2).collect();
+        let columns = ExtraColumns {document_frequency: Some(&df), probability: None};
+        let rev_map = [(1, 'ก')].iter().cloned().collect();
+        let mut parallel = vec![];
+        write_ngram_table(&mut parallel, &counts, OutputFormat::Csv, b'\t', &rev_map, true, columns).unwrap();
+        let mut serial = vec![];
+        write_ngram_counts(&mut serial, &counts, OutputFormat::Csv, b'\t', &rev_map, true, columns).unwrap();
+        assert_eq!(parallel, serial);
+        assert_eq!(parallel.iter().filter(|b| **b == b'\n').count(), counts.len() + 1);
+    }
+
+    #[test]
+    fn word_length_distribution() {
+        let lens = [(1, 1), (2, 2), (5, 1)].iter().cloned().collect();
+        assert_eq!(word_len_summary(&lens), Some((2.5, 2f64)));
+        let odd = [(1, 2), (4, 1)].iter().cloned().collect();
+        assert_eq!(word_len_summary(&odd), Some((2f64, 1f64)));
+        assert_eq!(word_len_summary(&HashMap::new()), None);
+        let path = write_corpus("word_lens.json", &[vec![vec![("กข", 1), ("ค", 1)], vec![("งก", 1)]]]);
+        let (vectorized, _) = vectorize_with(&thai_option(), &[path]).unwrap();
+        assert_eq!(vectorized.word_lens, [(1, 1), (2, 2)].iter().cloned().collect());
+    }
+
+    #[test]
+    fn concurrent_files_share_char_map() {
+        let thai: Vec<String> = "กขคงจฉชซฌญฎฏฐฑฒณดตถทธนบปผฝพฟภมยรลวศษสหฬอฮ".chars().map(|c| c.to_string()).collect();
+        let texts: Vec<String> = (0..8).map(|f| thai.iter().cycle().skip(f * 5).take(thai.len()).cloned().collect()).collect();
+        let corpuses: Vec<PathBuf> = texts.iter().enumerate().map(|(f, text)| {
+            write_corpus(&format!("concurrent_{}.json", f), &[vec![vec![(text.as_str(), 1)]]])
+        }).collect();
+        let (vectorized, map) = vectorize_with(&thai_option(), &corpuses).unwrap();
+        assert_eq!(map.len(), thai.len());
+        let mut assigned: Vec<Id> = map.values().cloned().collect();
+        assigned.sort_unstable();
+        assert_eq!(assigned, (1..=thai.len() as Id).collect::<Vec<Id>>());
+        let rev_map: HashMap<Id, char> = map.iter().map(|(c, v)| (*v, *c)).collect();
+        assert_eq!(decode_ngram(&vectorized.tagged, &rev_map), texts.concat());
+    }
+
+    #[test]
+    fn buffer_size_with_unit() {
+        assert_eq!(parse_buffer_size("4096"), Ok(4096));
+        assert_eq!(parse_buffer_size("8M"), Ok(8 * 1024 * 1024));
+        assert!(parse_buffer_size("0").is_err());
+        assert!(parse_buffer_size("abc").is_err());
+    }
+
+    #[test]
+    fn checkpoint_resume_match_uninterrupted_run() {
+        let a = write_corpus("checkpoint_a.json", &[
+            vec![vec![("กข", 1), ("ค", 2)]],
+            vec![vec![("ขค", 1)], vec![("ก", 1)]]
+        ]);
+        let b = write_corpus("checkpoint_b.json", &[vec![vec![("คงก", 1)]]]);
+        let option = thai_option();
+        let read = read_option();
+        let progress = ProgressBar::hidden();
+        let running = AtomicBool::new(false);
+
+        // uninterrupted run where a is vectorized before b
+        let init = RwLock::new(1);
+        let map = RwLock::new(HashMap::new());
+        let mut expected = vectorize(read, &option, std::slice::from_ref(&a), &init, &map, control(&progress, &running), None).unwrap();
+        let mut rest = vectorize(read, &option, std::slice::from_ref(&b), &init, &map, control(&progress, &running), None).unwrap();
+        expected.tagged.append(&mut rest.tagged);
+        expected.sentence_lens.append(&mut rest.sentence_lens);
+        expected.document_lens.append(&mut rest.document_lens);
+        expected.document_indices.append(&mut rest.document_indices);
+        expected.file_stats.append(&mut rest.file_stats);
+        let expected_map = map.into_inner().unwrap();
+        let expected_id = init.into_inner().unwrap();
+
+        // run interrupted after a then resumed on both files
+        let path = temp_path("checkpoint.txt");
+        let _ = std::fs::remove_file(&path);
+        let init = RwLock::new(1);
+        let map = RwLock::new(HashMap::new());
+        let checkpoint = Checkpoint::load(&path, &init, &map).unwrap();
+        assert!(checkpoint.is_empty());
+        vectorize(read, &option, std::slice::from_ref(&a), &init, &map, control(&progress, &running), Some(&checkpoint)).unwrap();
+        let init = RwLock::new(1);
+        let map = RwLock::new(HashMap::new());
+        let checkpoint = Checkpoint::load(&path, &init, &map).unwrap();
+        assert_eq!(checkpoint.len(), 1);
+        let resumed = vectorize(read, &option, &[a.clone(), b.clone()], &init, &map, control(&progress, &running), Some(&checkpoint)).unwrap();
+
+        assert_eq!(resumed.tagged, expected.tagged);
+        assert_eq!(resumed.sentence_lens, expected.sentence_lens);
+        assert_eq!(resumed.document_lens, expected.document_lens);
+        assert_eq!(resumed.document_indices, expected.document_indices);
+        assert_eq!(resumed.word_lens, [(1, 2), (2, 2), (3, 1)].iter().cloned().collect());
+        let stats = |v: &Vectorized| v.file_stats.iter().map(|s| (s.path.clone(), s.documents, s.sentences, s.words, s.chars)).collect::<Vec<_>>();
+        assert_eq!(stats(&resumed), stats(&expected));
+        assert_eq!(map.into_inner().unwrap(), expected_map);
+        assert_eq!(init.into_inner().unwrap(), expected_id);
+
+        // partially written last line is discarded
+        let len = std::fs::metadata(&path).unwrap().len();
+        std::fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"{\"path\":").unwrap();
+        let checkpoint = Checkpoint::load(&path, &RwLock::new(1), &RwLock::new(HashMap::new())).unwrap();
+        assert_eq!(checkpoint.len(), 2);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), len);
+        assert!(Checkpoint::load(&a, &RwLock::new(1), &RwLock::new(HashMap::new())).is_err());
+    }
+
+    #[test]
+    fn streaming_iter_match_vectorize() {
+        let path = write_corpus("streaming.json", &[
+            vec![vec![("กข", 1), ("ค", 2)], vec![("ขa", 1)]],
+            vec![vec![("งก", 1)]]
+        ]);
+        let option = thai_option();
+        let (vectorized, map) = vectorize_with(&option, std::slice::from_ref(&path)).unwrap();
+        let init = RwLock::new(1);
+        let iter_map = RwLock::new(HashMap::new());
+        let corpuses = [path];
+        let streamed = tagged_char_iter(read_option(), &option, &corpuses, &init, &iter_map).collect::<Result<Vec<(Id, Tag)>, String>>().unwrap();
+        assert_eq!(streamed, vectorized.tagged);
+        assert_eq!(iter_map.into_inner().unwrap(), map);
+        assert_eq!(vectorized.tagged, vec![(1, 0), (2, 1), (3, 2), (2, 0), (0, 1), (4, 0), (1, 1)]);
+    }
+
+    #[test]
+    fn unique_group_of_short_flatten() {
+        let raw: Vec<Id> = vec![1, 2, 1, 2, 3];
+        assert_eq!(get_unique_vecs_idx(2, &raw, &[]), Vec::<usize>::new());
+        assert_eq!(get_unique_vecs_idx(2, &raw, &[1]), vec![0]);
+        assert_eq!(get_unique_vecs_idx(2, &raw, &[0, 2]), vec![0]);
+        assert_eq!(get_unique_vecs_idx(2, &raw, &[0, 1]), vec![0, 1]);
+        // the last group is counted
+        assert_eq!(count_ngrams(2, &raw, &WindowOption {stride: 1, sentence_lens: None}), vec![(vec![1, 2], 2), (vec![2, 1], 1), (vec![2, 3], 1)]);
+        assert_eq!(count_ngrams(5, &raw, &WindowOption {stride: 1, sentence_lens: None}), vec![(raw.clone(), 1)]);
+    }
+
+    #[test]
+    fn char_list_line_skip_comment_and_blank() {
+        assert!(parse_char_list_line("# comment").unwrap().is_empty());
+        assert!(parse_char_list_line("").unwrap().is_empty());
+        assert!(parse_char_list_line("   ").unwrap().is_empty());
+        assert!(parse_char_list_line(" x").unwrap().is_empty());
+        assert_eq!(parse_char_list_line("0023-0023").unwrap(), vec!['#']);
+    }
+
+    #[test]
+    fn run_report_total_every_file() {
+        let stats = [file_stat("a", 2, 3, 10), file_stat("b", 1, 1, 4)];
+        let path = temp_path("report.json");
+        let settings = RunSettings {sample: None, boundaries: Boundaries::Sentence};
+        let stages = [("read", 3)];
+        write_report(&path, &stats, 7, Duration::default(), &stages, &settings, &[summary(5, None)]).unwrap();
+        let report: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!((report["files"].as_u64(), report["documents"].as_u64(), report["characters"].as_u64()), (Some(2), Some(3), Some(14)));
+        assert_eq!(report["boundaries"], "sentence");
+        assert_eq!(report["ngrams"][0]["unique"], 5);
+        assert_eq!(report["stages"]["read_ms"], 3);
+        assert!(!suffixed_path(&path, "tmp").exists());
+    }
+
+    #[test]
+    fn combined_pass_match_each_gram() {
+        let raw = seeded(600, 4, 11);
+        let lens = seeded_lens(raw.len());
+        for (stride, sentence_lens, max_ngrams) in [(1, None, None), (1, Some(lens.as_slice()), None), (2, Some(lens.as_slice()), None), (3, None, Some(5))].iter() {
+            let window = WindowOption {stride: *stride, sentence_lens: *sentence_lens};
+            let combined = count_ngrams_combined(&[3, 1, 2], &raw, &window, *max_ngrams, &mut CountTimes::default());
+            assert_eq!(combined.len(), 3);
+            for (gram, counted) in [3, 1, 2].iter().zip(combined) {
+                let expected = count_ngrams_timed(*gram, &raw, &window, *max_ngrams, &mut CountTimes::default());
+                assert_eq!(counted.counts, expected.counts);
+                assert_eq!((counted.unique, counted.windows), (expected.unique, expected.windows));
+            }
+        }
+        assert!(count_ngrams_combined::<Id>(&[], &raw, &WindowOption {stride: 1, sentence_lens: None}, None, &mut CountTimes::default()).is_empty());
+    }
+
+    #[test]
+    fn min_count_filter() {
+        let counts: NgramCounts<Id> = vec![(vec![1], 1), (vec![2], 3), (vec![3], 2)];
+        assert!(matches!(filter_min_count(&counts, 1), Cow::Borrowed(_)));
+        assert_eq!(filter_min_count(&counts, 2).into_owned(), vec![(vec![2], 3), (vec![3], 2)]);
+    }
+
+    #[test]
+    fn wrong_shape_explain_expected_shape() {
+        let read = |json: &str| for_each_corpus_document(json.as_bytes(), InputFormat::Json, |_| Ok(()));
+        assert!(read("{\"a\": 1}").unwrap_err().contains(CORPUS_SHAPE_HINT));
+        assert!(read("[[1]]").unwrap_err().contains(CORPUS_SHAPE_HINT));
+        assert!(read("[[[[[\"กข\"], 1]]]]").unwrap_err().contains(CORPUS_SHAPE_HINT));
+        assert!(!read("[[[").unwrap_err().contains(CORPUS_SHAPE_HINT));
+        let consumed = for_each_corpus_document("[[], []]".as_bytes(), InputFormat::Json, |_| Err("stop".to_owned()));
+        assert_eq!(consumed, Err("stop".to_owned()));
+    }
+
+    #[test]
+    fn word_tag_is_optional() {
+        let mut docs = vec![];
+        for_each_corpus_document("[[[[[\"ก\",\"ข\"],1],[\"ค\",\"ง\"],[[],2]]]]".as_bytes(), InputFormat::Json, |doc| {
+            docs.push(doc);
+            Ok(())
+        }).unwrap();
+        assert_eq!(docs, vec![vec![vec![("กข".to_owned(), 1), ("คง".to_owned(), 0), (String::new(), 2)]]]);
+    }
+
+    #[test]
+    fn directory_match_is_skipped() {
+        let dir = temp_path("glob_dir");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        let corpus = corpus_json(&[vec![vec![("กข", 1)]]]);
+        std::fs::write(dir.join("a.json"), &corpus).unwrap();
+        std::fs::write(dir.join("b.json"), &corpus).unwrap();
+        std::fs::write(dir.join("sub").join("c.json"), &corpus).unwrap();
+        let mut corpuses = expand_corpuses(&[dir.join("*").to_str().unwrap()]).unwrap();
+        corpuses.sort();
+        assert_eq!(corpuses, vec![dir.join("a.json"), dir.join("b.json")]);
+        let (vectorized, _) = vectorize_with(&thai_option(), &corpuses).unwrap();
+        assert_eq!(vectorized.file_stats.len(), 2);
+    }
+
+    #[test]
+    fn decode_ngram_back_to_chars() {
+        let rev_map = [(1, 'ก'), (2, 'ข')].iter().cloned().collect();
+        assert_eq!(decode_ngram(&[1 as Id, 2, 9], &rev_map), "กข\u{FFFD}");
+        assert_eq!(decode_ngram(&[(2 as Id, 1 as Tag), (1, 0)], &rev_map), "ขก");
+        assert_eq!(format_ids(&[(2 as Id, 1 as Tag), (1, 0)]), "2 1");
+        assert_eq!(format_tags(&[(2 as Id, 1 as Tag), (1, 0)]), "1 0");
+    }
+
+    #[test]
+    fn shards_concat_to_whole_table() {
+        let raw = seeded(300, 5, 3);
+        let counts = count_ngrams(2, &raw, &WindowOption {stride: 1, sentence_lens: None});
+        let shards = shard_by_prefix(&counts);
+        assert_eq!(shards.iter().map(|(prefix, _)| *prefix).collect::<Vec<Id>>(), vec![1, 2, 3, 4, 5]);
+        assert!(shards.iter().all(|(prefix, shard)| shard.iter().all(|(ngram, _)| ngram[0] == *prefix)));
+        assert_eq!(shards.iter().flat_map(|(_, shard)| shard.iter().cloned()).collect::<NgramCounts<Id>>(), counts);
+        assert!(shard_by_prefix::<Id>(&[]).is_empty());
+    }
+
+    #[test]
+    fn oov_windows_of_mixed_script() {
+        let path = write_corpus("oov_windows.json", &[vec![vec![("กขaค", 1)], vec![("bง", 1)]]]);
+        let mut option = thai_option();
+        option.oov_mode = OovMode::Token;
+        let (vectorized, _) = vectorize_with(&option, &[path]).unwrap();
+        let vecs = ids(&vectorized.tagged);
+        let oov_id = option.oov_mode.oov_id(option.id_width).unwrap();
+        assert_eq!(vecs, vec![1, 2, oov_id, 3, oov_id, 4]);
+        let window = WindowOption {stride: 1, sentence_lens: Some(&vectorized.sentence_lens)};
+        assert_eq!(count_oov_windows(2, &vecs, &window, oov_id), 3);
+        assert_eq!(count_oov_windows(1, &vecs, &window, oov_id), 2);
+        assert_eq!(oov_windows(&count_ngrams(2, &vecs, &window), oov_id), 3);
+        let mut counted = count_ngrams_timed(2, &vecs, &window, Some(1), &mut CountTimes::default());
+        add_oov_windows(&mut counted, 2, &vecs, &window, oov_id);
+        assert_eq!(counted.oov_windows, Some(3));
+    }
+
+    #[test]
+    fn stage_times_of_every_stage() {
+        let mut stat = file_stat("a", 1, 1, 1);
+        stat.parse_time = Duration::from_millis(10);
+        stat.vectorize_time = Duration::from_millis(4);
+        let mut ngram = summary(1, None);
+        ngram.count_times = CountTimes {flatten: Duration::from_millis(1), sort: Duration::from_millis(2), group: Duration::from_millis(3)};
+        ngram.write_time = Duration::from_millis(5);
+        let stages = stage_times(Duration::from_millis(7), &[stat], &[ngram]);
+        assert_eq!(stages, vec![("expand", 7), ("read", 6), ("vectorize", 4), ("flatten", 1), ("sort", 2), ("group", 3), ("write", 5)]);
+    }
+
+    #[test]
+    fn max_ngrams_keep_most_frequent() {
+        let raw: Vec<Id> = vec![1, 1, 1, 2, 2, 3, 3, 4];
+        let window = WindowOption {stride: 1, sentence_lens: None};
+        let counted = count_ngrams_timed(1, &raw, &window, Some(2), &mut CountTimes::default());
+        assert_eq!(counted.counts, vec![(vec![1], 3), (vec![2], 2)]);
+        assert_eq!((counted.unique, counted.windows), (4, 8));
+        let all = count_ngrams_timed(1, &raw, &window, Some(10), &mut CountTimes::default());
+        assert_eq!(all.counts, count_ngrams(1, &raw, &window));
+    }
+
+    #[test]
+    fn tag_map_apply_before_filter() {
+        assert_eq!(parse_tag_map_line("5 = 4"), Ok(Some((5, 4))));
+        assert_eq!(parse_tag_map_line("  # comment"), Ok(None));
+        assert_eq!(parse_tag_map_line(""), Ok(None));
+        assert!(parse_tag_map_line("5=0").is_err());
+        assert!(parse_tag_map_line("x=1").is_err());
+        assert!(parse_tag_map_line("5").is_err());
+        let path = write_corpus("tag_map.json", &[vec![vec![("ก", 1), ("ข", 2), ("ค", 3)]]]);
+        let mut option = thai_option();
+        option.tag_map = [(2, 1)].iter().cloned().collect();
+        option.tag_filter = TagFilter::Only(vec![1]);
+        assert_eq!(vectorize_with(&option, &[path]).unwrap().0.tagged, vec![(1, 1), (2, 1)]);
+    }
+
+    #[test]
+    fn per_file_names_are_distinct() {
+        let stats = [file_stat("a/x.json", 1, 1, 1), file_stat("b/x.json", 1, 1, 1), file_stat("-", 1, 1, 1), file_stat("y", 1, 1, 1)];
+        assert_eq!(per_file_names(&stats), vec!["x", "x_1", "stdin", "y"]);
+        assert_eq!(suffixed_path(Path::new("out/res.csv"), "intra_word"), PathBuf::from("out/res.intra_word.csv"));
+        assert_eq!(suffixed_path(Path::new("res"), "x"), PathBuf::from("res.x"));
+    }
+
+    #[test]
+    fn per_file_tables_merge_to_aggregate() {
+        let a = write_corpus("per_file_a.json", &[vec![vec![("กข", 1), ("ค", 1)], vec![("ขค", 1)]]]);
+        let b = write_corpus("per_file_b.json", &[vec![vec![("คกข", 1)]], vec![vec![("กข", 1)]]]);
+        let (vectorized, _) = vectorize_with(&thai_option(), &[a, b]).unwrap();
+        let vecs = ids(&vectorized.tagged);
+        let window = WindowOption {stride: 1, sentence_lens: Some(&vectorized.sentence_lens)};
+        let option = report_option(temp_path("per_file.csv"));
+        let mut log = vec![];
+        let aggregate = analyze_ngrams(2, &vecs, &window, &option, &mut log).unwrap();
+        let summaries = analyze_per_file(2, &vecs, &window, &vectorized.file_stats, &option, &mut log).unwrap();
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(summaries.iter().map(|s| s.windows).sum::<usize>(), aggregate.windows);
+        let mut merged = HashMap::new();
+        for name in per_file_names(&vectorized.file_stats) {
+            for (ngram, count) in csv_counts(&suffixed_path(&option.out_path, &name)) {
+                *merged.entry(ngram).or_insert(0) += count;
+            }
+        }
+        assert_eq!(merged, csv_counts(&option.out_path));
+    }
+
+    #[test]
+    fn exit_code_of_run_outcome() {
+        let stats = [file_stat("a", 1, 1, 1)];
+        assert_eq!(run_outcome(1, &stats, &[summary(3, None)]), Ok(()));
+        let skipped = run_outcome(2, &stats, &[summary(3, None)]).unwrap_err();
+        assert_eq!((skipped.clone(), skipped.exit_code()), (RunError::SkippedFiles(1), 2));
+        let empty = run_outcome(2, &stats, &[summary(0, None), summary(0, Some(1))]).unwrap_err();
+        assert_eq!((empty.clone(), empty.exit_code()), (RunError::NoNgrams, 3));
+        // gram longer than every sentence is warned instead
+        assert_eq!(run_outcome(1, &stats, &[summary(0, Some(1))]), Ok(()));
+        assert_eq!(RunError::Fatal(String::new()).exit_code(), 1);
+        assert_eq!(RunError::Io(String::new()).exit_code(), 2);
+        assert_eq!(RunError::Interrupted.exit_code(), 130);
+        assert_eq!(RunError::from(AnalyzeError::Interrupted), RunError::Interrupted);
+    }
+
+    #[test]
+    fn merge_maps_reconcile_ids() {
+        let a = [('a', 1), ('b', 2)].iter().cloned().collect();
+        let b = [('b', 1), ('c', 2), ('d', 3)].iter().cloned().collect();
+        let (merged, remap) = merge_maps(a, b);
+        assert_eq!(merged, [('a', 1), ('b', 2), ('c', 3), ('d', 4)].iter().cloned().collect());
+        assert_eq!(remap, [(1, 2), (2, 3), (3, 4)].iter().cloned().collect());
+        let (merged, remap) = merge_maps(HashMap::new(), [('x', 7)].iter().cloned().collect());
+        assert_eq!((merged[&'x'], remap[&7]), (1, 1));
+    }
+
+    #[test]
+    fn bom_and_trailing_whitespace_are_skipped() {
+        let mut content = UTF8_BOM.to_vec();
+        content.extend_from_slice(corpus_json(&[vec![vec![("กข", 1)]]]).as_bytes());
+        content.extend_from_slice(b"\n \t\r\n");
+        let path = write_temp("bom.json", &content);
+        let mut docs = 0;
+        read_corpus(read_option(), &path, |_| {
+            docs += 1;
+            Ok(())
+        }).unwrap();
+        assert_eq!(docs, 1);
+    }
+
+    #[test]
+    fn benchmarked_windows_functions() {
+        // the same shape of input as get_unique_vecs_idx and sorted_windows benchmark
+        let vecs = seeded(20_000, 46, 42);
+        let window = WindowOption {stride: 1, sentence_lens: None};
+        for gram in 1..=5u8 {
+            let g = gram as usize;
+            let starts = window_starts(g, vecs.len(), &window);
+            let flatten = sorted_windows(gram, &vecs, starts.clone());
+            assert!(flatten.windows(2).all(|pair| vecs[pair[0]..(pair[0] + g)] <= vecs[pair[1]..(pair[1] + g)]));
+            let mut permutation = flatten.clone();
+            permutation.sort_unstable();
+            assert_eq!(permutation, starts);
+
+            let unique_idx = get_unique_vecs_idx(gram, &vecs, &flatten);
+            let distinct: HashSet<&[Id]> = starts.iter().map(|i| &vecs[*i..(*i + g)]).collect();
+            assert_eq!(unique_idx.len(), distinct.len());
+            assert!(unique_idx.windows(2).all(|pair| {
+                let (a, b) = (flatten[pair[0]], flatten[pair[1]]);
+                vecs[a..(a + g)] < vecs[b..(b + g)]
+            }));
+        }
+    }
+
+    #[test]
+    fn sample_is_reproducible() {
+        let path = Path::new("corpus.json");
+        let all = Sample {fraction: 1f64, seed: 7};
+        assert!((0..100).all(|i| all.keep(path, i)));
+        let half = Sample {fraction: 0.5, seed: 7};
+        let kept: Vec<usize> = (0..1000).filter(|i| half.keep(path, *i)).collect();
+        assert!(kept.len() > 400 && kept.len() < 600, "{}", kept.len());
+        assert_eq!(kept, (0..1000).filter(|i| half.keep(path, *i)).collect::<Vec<usize>>());
+        let other_seed = Sample {fraction: 0.5, seed: 8};
+        assert_ne!(kept, (0..1000).filter(|i| other_seed.keep(path, *i)).collect::<Vec<usize>>());
+        assert_eq!(parse_sample_fraction("1"), Ok(1f64));
+        assert!(parse_sample_fraction("0").is_err());
+        assert!(parse_sample_fraction("1.5").is_err());
+    }
+
+    #[test]
+    fn longest_repeat_inside_sentence() {
+        let vecs = [1, 2, 3, 1, 2, 3, 4];
+        assert_eq!(longest_repeat(&vecs, None), Some((0, 3)));
+        assert_eq!(longest_repeat(&vecs, Some(&[3, 4])), Some((0, 3)));
+        assert_eq!(longest_repeat(&vecs, Some(&[4, 3])), Some((1, 2)));
+        // occurrences may overlap
+        assert_eq!(longest_repeat(&[5, 5, 5], None), Some((0, 2)));
+        assert_eq!(longest_repeat(&[1, 2, 3], None), None);
+        assert_eq!(longest_repeat(&[], None), None);
+    }
+
+    #[test]
+    fn document_frequency_count_each_document_once() {
+        let raw: Vec<Id> = vec![1, 2, 1, 2, 3, 1, 2];
+        let document_lens = [5, 2];
+        let window = WindowOption {stride: 1, sentence_lens: Some(&document_lens)};
+        let mut counted = count_ngrams_timed(2, &raw, &window, None, &mut CountTimes::default());
+        add_document_frequency(&mut counted, 2, &raw, &window, &document_lens);
+        assert_eq!(counted.counts, vec![(vec![1, 2], 3), (vec![2, 1], 1), (vec![2, 3], 1)]);
+        assert_eq!(counted.document_frequency, Some(vec![2, 1, 1]));
+    }
+
+    #[test]
+    fn gram_longer_than_every_sentence() {
+        let lens = [2, 3];
+        assert_eq!(gram_exceeds_sentences(4, &WindowOption {stride: 1, sentence_lens: Some(&lens)}), Some(3));
+        assert_eq!(gram_exceeds_sentences(3, &WindowOption {stride: 1, sentence_lens: Some(&lens)}), None);
+        assert_eq!(gram_exceeds_sentences(9, &WindowOption {stride: 1, sentence_lens: None}), None);
+    }
+
+    #[test]
+    fn ignore_boundaries_treat_corpus_as_one_sequence() {
+        assert_eq!(Boundaries::Ignore.segment_lens(&[1], &[2]), None);
+        let raw: Vec<Id> = vec![1, 2, 3, 1, 2];
+        let lens = [3, 2];
+        let window = WindowOption {stride: 1, sentence_lens: Boundaries::Ignore.segment_lens(&lens, &[5])};
+        assert_eq!(count_ngrams(2, &raw, &window), vec![(vec![1, 2], 2), (vec![2, 3], 1), (vec![3, 1], 1)]);
+        assert_eq!("ignore".parse::<Boundaries>(), Ok(Boundaries::Ignore));
+    }
+
+    #[test]
+    fn unique_ngrams_are_sorted_and_distinct() {
+        let raw = seeded(400, 3, 5);
+        let lens = seeded_lens(raw.len());
+        let window = WindowOption {stride: 1, sentence_lens: Some(&lens)};
+        let unique = unique_ngrams(3, &raw, &window);
+        assert!(unique.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(unique, count_ngrams(3, &raw, &window).into_iter().map(|(ngram, _)| ngram).collect::<Vec<Vec<Id>>>());
+    }
+
+    #[test]
+    fn csv_delimiter_and_quoting() {
+        assert_eq!(parse_delimiter("\\t"), Ok(b'\t'));
+        assert_eq!(parse_delimiter(";"), Ok(b';'));
+        assert!(parse_delimiter("\"").is_err());
+        assert!(parse_delimiter("ab").is_err());
+        assert!(parse_delimiter("ก").is_err());
+        let counts: NgramCounts<Id> = vec![(vec![1, 2], 2)];
+        let rev_map = [(1, ';'), (2, '"')].iter().cloned().collect();
+        let mut out = vec![];
+        write_ngram_table(&mut out, &counts, OutputFormat::Csv, b';', &rev_map, true, ExtraColumns::default()).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "ngram;chars;count\n1 2;\";\"\"\";2\n");
+    }
+
+    #[test]
+    fn document_range_across_files() {
+        let corpuses = [PathBuf::from("a"), PathBuf::from("b")];
+        let range = DocRange::new(parse_doc_range("1:3").unwrap(), &corpuses, &[2, 2]);
+        let kept: Vec<(&str, usize)> = [("a", 0), ("a", 1), ("b", 0), ("b", 1), ("c", 1)].iter().cloned().filter(|(f, i)| range.keep(Path::new(f), *i)).collect();
+        assert_eq!(kept, vec![("a", 1), ("b", 0)]);
+        assert!(parse_doc_range("3:3").is_err());
+        assert!(parse_doc_range("3").is_err());
+        let path = write_corpus("doc_range.json", &[vec![vec![("ก", 1)]], vec![vec![("ข", 1)]], vec![vec![("ค", 1)]]]);
+        let mut option = thai_option();
+        option.doc_range = Some(DocRange::new(1..2, std::slice::from_ref(&path), &count_documents(read_option(), std::slice::from_ref(&path))));
+        let (vectorized, map) = vectorize_with(&option, &[path]).unwrap();
+        assert_eq!(vectorized.document_indices, vec![1]);
+        assert_eq!(map, [('ข', 1)].iter().cloned().collect());
+    }
+
+    #[test]
+    fn duplicate_documents_ignore_tag() {
+        let tagged = [(1, 0), (2, 1), (3, 1), (1, 0), (2, 2), (1, 0), (2, 1)];
+        let stats = [file_stat("a", 3, 3, 5), file_stat("b", 2, 2, 2)];
+        let groups = duplicate_documents(&tagged, &[2, 1, 2, 0, 2], &[0, 1, 2, 0, 1], &stats);
+        assert_eq!(groups, vec![vec![(Path::new("a"), 0), (Path::new("a"), 2), (Path::new("b"), 1)]]);
+    }
+
+    #[test]
+    fn tag_exceeding_width_is_error() {
+        let path = write_corpus("tag_width.json", &[vec![vec![("ก", 300)]]]);
+        let err = vectorize_with(&thai_option(), std::slice::from_ref(&path)).err().unwrap();
+        assert!(err.contains("Tag 300 exceed u8 tag width"), "{}", err);
+        let mut option = thai_option();
+        option.tag_width = TagWidth::U16;
+        assert_eq!(vectorize_with(&option, &[path]).unwrap().0.tagged, vec![(1, 300)]);
+    }
+
+    #[test]
+    fn frequency_of_frequencies() {
+        let counts: NgramCounts<Id> = vec![(vec![1], 1), (vec![2], 2), (vec![3], 1)];
+        assert_eq!(freq_of_freq(&counts), [(1, 2), (2, 1)].iter().cloned().collect());
+        let path = temp_path("fof.csv");
+        write_freq_of_freq(&path, &freq_of_freq(&counts)).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "count,ngrams\n1,2\n2,1\n");
+    }
+
+    #[test]
+    fn interrupt_after_one_file() {
+        let a = write_corpus("interrupt_a.json", &[vec![vec![("กข", 1)]]]);
+        let b = write_corpus("interrupt_b.json", &[vec![vec![("คง", 1)]]]);
+        let path = temp_path("interrupt_checkpoint.txt");
+        let _ = std::fs::remove_file(&path);
+        let progress = ProgressBar::hidden();
+        let interrupted = AtomicBool::new(false);
+        let init = RwLock::new(1);
+        let map = RwLock::new(HashMap::new());
+        let checkpoint = Checkpoint::load(&path, &init, &map).unwrap();
+        vectorize(read_option(), &thai_option(), std::slice::from_ref(&a), &init, &map, control(&progress, &interrupted), Some(&checkpoint)).unwrap();
+
+        // a is completed before the flag is set so only b is skipped
+        interrupted.store(true, Ordering::SeqCst);
+        let init = RwLock::new(1);
+        let map = RwLock::new(HashMap::new());
+        let checkpoint = Checkpoint::load(&path, &init, &map).unwrap();
+        let vectorized = vectorize(read_option(), &thai_option(), &[a.clone(), b.clone()], &init, &map, control(&progress, &interrupted), Some(&checkpoint)).unwrap();
+        assert_eq!(vectorized.file_stats.iter().map(|s| s.path.clone()).collect::<Vec<_>>(), vec![a.clone()]);
+        assert_eq!(ids(&vectorized.tagged), vec![1, 2]);
+
+        let config = AnalyzeConfig::new(vec![1], vec![a.clone(), b.clone()]);
+        config.interrupted.store(true, Ordering::SeqCst);
+        assert_eq!(analyze(config).err(), Some(AnalyzeError::Interrupted));
+        // flag of another run is left untouched
+        let result = analyze(AnalyzeConfig::new(vec![1], vec![a, b])).unwrap();
+        assert_eq!(result.vectorized.file_stats.len(), 2);
+    }
+
+    #[test]
+    fn failed_atomic_write_leave_no_file() {
+        let path = temp_path("atomic.csv");
+        let temp = suffixed_path(&path, "tmp");
+        let result = write_atomically(&path, |mut file| {
+            file.write_all(b"partial")?;
+            Err(std::io::Error::other("fail"))
+        });
+        assert!(result.is_err());
+        assert!(!path.exists() && !temp.exists());
+
+        // rename onto a non-empty directory fail
+        let dir = temp_path("atomic_dir");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        assert!(write_atomically(&dir, |mut file| file.write_all(b"done")).is_err());
+        assert!(!suffixed_path(&dir, "tmp").exists());
+        write_atomically(&path, |mut file| file.write_all(b"done")).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "done");
+        assert!(!temp.exists());
+    }
+
+    #[test]
+    fn utf16_and_tis620_are_transcoded() {
+        let json = corpus_json(&[vec![vec![("กขa", 1)]]]);
+        let utf16 = |bom: [u8; 2], to_bytes: fn(u16) -> [u8; 2]| {
+            let mut bytes = bom.to_vec();
+            bytes.extend(json.encode_utf16().flat_map(to_bytes));
+            bytes
+        };
+        let cases = [
+            (Encoding::Utf16Le, utf16([0xFF, 0xFE], u16::to_le_bytes)),
+            (Encoding::Utf16Be, utf16([0xFE, 0xFF], u16::to_be_bytes)),
+            (Encoding::Utf16Le, json.encode_utf16().flat_map(u16::to_le_bytes).collect()),
+            (Encoding::Tis620, json.replace('ก', "\u{1}").replace('ข', "\u{2}").bytes().map(|b| match b { 1 => 0xA1, 2 => 0xA2, b => b }).collect())
+        ];
+        for (i, (encoding, bytes)) in cases.iter().enumerate() {
+            let path = write_temp(&format!("encoding_{}.json", i), bytes);
+            // tiny buffer split code unit across reads
+            let mut decoded = String::new();
+            open_corpus(&path, 3, *encoding).unwrap().read_to_string(&mut decoded).unwrap();
+            assert_eq!(decoded, json, "{:?}", encoding);
+            let read = ReadOption {buf_size: 3, format: InputFormat::Json, encoding: *encoding, strict: true};
+            let mut words = vec![];
+            read_corpus(read, &path, |doc| {
+                words.extend(doc.into_iter().flatten());
+                Ok(())
+            }).unwrap();
+            assert_eq!(words, vec![("กขa".to_owned(), 1)]);
+        }
+    }
+
+    #[test]
+    fn parallel_dedup_match_serial_dedup() {
+        // small alphabet make most windows tie so equal windows span across rayon splits
+        let raw = seeded(50_000, 2, 29);
+        let tagged: Vec<(Id, Tag)> = raw.iter().zip(seeded(raw.len(), 2, 31)).map(|(v, t)| (*v, t as Tag)).collect();
+        for gram in [1u8, 3, 8].iter() {
+            let g = *gram as usize;
+            let starts = window_starts(g, raw.len(), &WindowOption {stride: 1, sentence_lens: None});
+            let mut serial = starts.clone();
+            serial.sort_unstable_by(|a, b| raw[*a..(*a + g)].cmp(&raw[*b..(*b + g)]));
+            let serial_groups: Vec<usize> = (0..serial.len()).filter(|i| *i == 0 || raw[serial[i - 1]..(serial[i - 1] + g)] != raw[serial[*i]..(serial[*i] + g)]).collect();
+
+            let parallel = sorted_windows(*gram, &raw, starts.clone());
+            // starts of tied windows may be in any order but their windows are identical
+            assert!(parallel.iter().zip(&serial).all(|(p, s)| raw[*p..(*p + g)] == raw[*s..(*s + g)]));
+            assert_eq!(get_unique_vecs_idx(*gram, &raw, &parallel), serial_groups);
+            assert_eq!(get_unique_vecs_idx(*gram, &raw, &serial), serial_groups);
+
+            let mut serial = starts.clone();
+            serial.sort_unstable_by(|a, b| tagged[*a..(*a + g)].cmp(&tagged[*b..(*b + g)]));
+            let serial_groups = (0..serial.len()).filter(|i| *i == 0 || tagged[serial[i - 1]..(serial[i - 1] + g)] != tagged[serial[*i]..(serial[*i] + g)]).count();
+            assert_eq!(get_unique_vecs_idx(*gram, &tagged, &sorted_windows(*gram, &tagged, starts)).len(), serial_groups);
+        }
+    }
+
+    #[test]
+    fn include_list_fold_case() {
+        assert_eq!(fold_case('A'), 'a');
+        assert_eq!(fold_case('ก'), 'ก');
+        // lowercase of U+0130 is two chars so it is kept as is
+        assert_eq!(fold_case('\u{130}'), '\u{130}');
+        let path = write_corpus("fold_case.json", &[vec![vec![("Aaก", 1)]]]);
+        let mut option = thai_option();
+        option.char_include_list = vec!['a', 'A'];
+        assert_eq!(vectorize_with(&option, std::slice::from_ref(&path)).unwrap().0.tagged, vec![(1, 0), (2, 0), (3, 1)]);
+        option.fold_case = true;
+        let (vectorized, map) = vectorize_with(&option, &[path]).unwrap();
+        assert_eq!(vectorized.tagged, vec![(1, 0), (1, 0), (2, 1)]);
+        assert!(!map.contains_key(&'A'));
+    }
+
+    #[test]
+    fn positional_bucket_of_window() {
+        assert_eq!(positional_window_starts(2, &[4, 2, 1], 1), (vec![0, 4], vec![1], vec![2, 4]));
+        assert_eq!(positional_window_starts(2, &[7], 2), (vec![0], vec![2], vec![4]));
+        let raw: Vec<Id> = vec![1, 2, 3, 4, 1, 2, 9];
+        let (start, middle, end) = count_ngrams_by_position(2, &raw, &[4, 2, 1], 1);
+        assert_eq!(start, vec![(vec![1, 2], 2)]);
+        assert_eq!(middle, vec![(vec![2, 3], 1)]);
+        assert_eq!(end, vec![(vec![1, 2], 1), (vec![3, 4], 1)]);
+    }
+
+    #[test]
+    fn append_write_header_once() {
+        let path = temp_path("append.csv");
+        let _ = std::fs::remove_file(&path);
+        let counts: NgramCounts<Id> = vec![(vec![1], 2), (vec![2], 1)];
+        let rev_map = HashMap::new();
+        append_ngram_table(&path, &counts, b',', &rev_map, false, ExtraColumns::default()).unwrap();
+        append_ngram_table(&path, &counts[1..], b',', &rev_map, false, ExtraColumns::default()).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "ngram,count\n1,2\n2,1\n2,1\n");
+    }
+
+    #[test]
+    fn analyze_count_every_gram() {
+        let path = write_corpus("analyze.json", &[
+            vec![vec![("กข", 1), ("ค", 1)], vec![("กข", 1)]],
+            vec![vec![("กข", 1), ("ค", 1)]]
+        ]);
+        let mut config = AnalyzeConfig::new(vec![1, 2], vec![path.clone()]);
+        config.boundaries = Boundaries::Sentence;
+        config.drop_repeated_sentences = true;
+        let result = analyze(config).unwrap();
+        assert_eq!(result.next_id, 4);
+        assert_eq!(result.char_map.len(), 3);
+        assert_eq!(result.dropped_sentences, vec![(vec![1, 2, 3], 2)]);
+        assert_eq!(result.vectorized.sentence_lens, vec![3, 2]);
+        assert_eq!(result.ngrams.iter().map(|n| n.gram).collect::<Vec<u8>>(), vec![1, 2]);
+        match &result.ngrams[1].table {
+            NgramTable::Chars(counted) => assert_eq!(counted.counts, vec![(vec![1, 2], 2), (vec![2, 3], 1)]),
+            NgramTable::Tagged(_) => panic!("n-gram should not carry tag")
+        }
+
+        let mut config = AnalyzeConfig::new(vec![2], vec![path.clone()]);
+        config.with_tags = true;
+        let result = analyze(config).unwrap();
+        assert!(matches!(&result.ngrams[0].table, NgramTable::Tagged(counted) if counted.windows == 7));
+
+        let mut config = AnalyzeConfig::new(vec![2], vec![path]);
+        config.stride = 0;
+        assert!(matches!(analyze(config), Err(AnalyzeError::Config(_))));
+        let mut config = AnalyzeConfig::new(vec![2], vec![PathBuf::from("-")]);
+        config.checkpoint = Some(temp_path("analyze_checkpoint.txt"));
+        assert!(matches!(analyze(config), Err(AnalyzeError::Checkpoint(_))));
+    }
+
+    #[test]
+    fn vectors_round_trip_in_minimal_width() {
+        assert_eq!(min_bytes(0), 1);
+        assert_eq!(min_bytes(255), 1);
+        assert_eq!(min_bytes(256), 2);
+        assert_eq!(min_bytes(65_536), 3);
+        assert_eq!(min_bytes(u32::MAX as u64), 4);
+        for (i, (max_id, max_tag, width, tag_bytes)) in [(200, 3, 1, 1), (60_000, 300, 2, 2), (70_000, 1, 3, 1), (u32::MAX, 0, 4, 1)].iter().enumerate() {
+            let tagged: Vec<(Id, Tag)> = vec![(0, 0), (*max_id, *max_tag), (max_id / 2, 1), (1, 0)];
+            let path = temp_path(&format!("vectors_{}.bin", i));
+            write_vectors(&path, &tagged).unwrap();
+            let bytes = std::fs::read(&path).unwrap();
+            assert_eq!(&bytes[..6], &[b'B', b'S', b'T', b'V', *width, *tag_bytes]);
+            assert_eq!(bytes.len(), 14 + tagged.len() * (*width as usize + *tag_bytes as usize));
+            assert_eq!(read_vectors(&path).unwrap(), tagged);
+        }
+    }
+
+    #[test]
+    fn read_vectors_reject_inconsistent_header() {
+        let path = temp_path("vectors_forged.bin");
+        write_vectors(&path, &[(1, 1), (2, 1)]).unwrap();
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[6] = 200;
+        std::fs::write(&path, &bytes).unwrap();
+        assert_eq!(read_vectors(&path).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        bytes[6] = 2;
+        bytes[4] = 5;
+        std::fs::write(&path, &bytes).unwrap();
+        assert_eq!(read_vectors(&path).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn repeated_sentences_are_dropped() {
+        let tagged = vec![(1, 0), (2, 1), (3, 1), (1, 0), (2, 1), (1, 0), (2, 2)];
+        let mut vectorized = Vectorized {
+            tagged,
+            sentence_lens: vec![2, 1, 0, 2, 2],
+            document_lens: vec![3, 4],
+            file_stats: vec![file_stat("a", 2, 5, 7)],
+            ..Vectorized::default()
+        };
+        assert_eq!(repeated_sentences(&vectorized.tagged, &vectorized.sentence_lens), vec![(vec![1, 2], 3)]);
+        assert_eq!(drop_repeated_sentences(&mut vectorized), vec![(vec![1, 2], 3)]);
+        assert_eq!(vectorized.tagged, vec![(1, 0), (2, 1), (3, 1)]);
+        assert_eq!(vectorized.sentence_lens, vec![2, 1, 0]);
+        assert_eq!(vectorized.document_lens, vec![3, 0]);
+        assert_eq!((vectorized.file_stats[0].sentences, vectorized.file_stats[0].chars), (3, 3));
+    }
+
+    #[test]
+    fn good_turing_mass_sum_to_one() {
+        let mut counts: NgramCounts<Id> = vec![];
+        for (r, n) in [(1, 10), (2, 5), (3, 3), (4, 2), (6, 1)].iter() {
+            for _ in 0..*n {
+                counts.push((vec![counts.len() as Id], *r));
+            }
+        }
+        let estimate = good_turing(&counts, &freq_of_freq(&counts));
+        assert!((estimate.unseen - 10f64 / 43f64).abs() < 1e-12);
+        assert!((estimate.probabilities.iter().sum::<f64>() + estimate.unseen - 1f64).abs() < 1e-9);
+        // n-gram seen more often is more probable
+        assert!(estimate.probabilities[0] < estimate.probabilities[counts.len() - 1]);
+
+        let single: NgramCounts<Id> = vec![(vec![1], 2), (vec![2], 2)];
+        let estimate = good_turing(&single, &freq_of_freq(&single));
+        assert_eq!((estimate.probabilities, estimate.unseen), (vec![0.5, 0.5], 0f64));
+        assert!(good_turing::<Id>(&[], &BTreeMap::new()).probabilities.is_empty());
+
+        // more n-grams occur twice than once so the fitted slope is above -1
+        let flat: NgramCounts<Id> = vec![(vec![1], 1), (vec![2], 2), (vec![3], 2)];
+        let estimate = good_turing(&flat, &freq_of_freq(&flat));
+        assert!((estimate.unseen - 0.2).abs() < 1e-12);
+        let expected = [0.8 / 5f64, 1.6 / 5f64, 1.6 / 5f64];
+        assert!(estimate.probabilities.iter().zip(expected.iter()).all(|(p, e)| (p - e).abs() < 1e-12), "{:?}", estimate.probabilities);
+    }
+
+    #[test]
+    fn equivalence_class_share_id() {
+        assert_eq!(parse_equiv_class("0123456789=DIGIT").unwrap().chars.len(), 10);
+        assert_eq!(parse_equiv_class("aab==EQ").unwrap(), EquivClass {name: "EQ".to_owned(), chars: vec!['a', 'b', '=']});
+        assert!(parse_equiv_class("=X").is_err());
+        assert!(parse_equiv_class("abc=").is_err());
+        assert!(parse_equiv_class("abc").is_err());
+        let overlap = EquivClasses::new(vec![parse_equiv_class("01=A").unwrap(), parse_equiv_class("12=B").unwrap()]);
+        assert!(overlap.unwrap_err().contains("belong to both"));
+        let same_name = EquivClasses::new(vec![parse_equiv_class("01=A").unwrap(), parse_equiv_class("23=A").unwrap()]);
+        assert!(same_name.is_err());
+
+        let path = write_corpus("equiv_class.json", &[vec![vec![("1ก2", 1)]]]);
+        let mut option = thai_option();
+        option.equiv_classes = EquivClasses::new(vec![parse_equiv_class("0123456789=DIGIT").unwrap()]).unwrap();
+        let (vectorized, map) = vectorize_with(&option, &[path]).unwrap();
+        assert_eq!(vectorized.tagged, vec![(1, 0), (2, 0), (1, 1)]);
+        assert_eq!(map, [('0', 1), ('ก', 2)].iter().cloned().collect());
+    }
+
+    #[test]
+    fn brute_force_agree_with_sort_dedup() {
+        let raw = seeded(500, 3, 17);
+        let lens = seeded_lens(raw.len());
+        for gram in 1..=5 {
+            for sentence_lens in [None, Some(lens.as_slice())].iter() {
+                let window = WindowOption {stride: 1, sentence_lens: *sentence_lens};
+                let counted = count_ngrams_timed(gram, &raw, &window, Some(3), &mut CountTimes::default());
+                assert_eq!(brute_force_unique(gram, &raw, &window), counted.unique);
+                assert_eq!(unique_ngrams(gram, &raw, &window).len(), counted.unique);
+            }
+        }
+        let path = write_corpus("verify.json", &[vec![vec![("กขกข", 1)], vec![("ขก", 1)]]]);
+        let mut config = AnalyzeConfig::new(vec![1, 2, 3], vec![path]);
+        config.verify = true;
+        let unique: Vec<usize> = analyze(config).unwrap().ngrams.iter().map(|n| match &n.table {
+            NgramTable::Chars(counted) => counted.unique,
+            NgramTable::Tagged(counted) => counted.unique
+        }).collect();
+        assert_eq!(unique, vec![2, 3, 4]);
+    }
+}
@@ -1,151 +1,139 @@
 use clap::{Arg, App};
-use glob::glob;
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{debug, info, warn, Level, LevelFilter};
 use rayon::prelude::*;
-use serde_json::from_reader;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::iter::Iterator;
+use std::io::{BufRead, BufReader, Write};
 use std::path::{Path, PathBuf};
-use std::sync::{RwLock};
-use std::time::{Instant};
-use unbytify::unbytify;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
-type Corpus = Vec<          // Documents
-    Vec<                    // Sentences
-        Vec<                // Words
-            (
-                Vec<char>,  // character
-                u8          // tag
-            )
-        >
-    >
->;
+use best_analysis::*;
 
-/// Vectorize all `corpuses` in given paths using pre-defined `map`.
-/// If char in corpus is not exist in the map, it'll use `init` as 
-/// vectorized value then assign new `char` and `init` into map.
-/// It then increase init by 1.
-/// If corpus contain non-Thai characters and it need to be vectorized
-/// then it has to be in char_include_list.
-/// 
-/// # Parameter
-/// - `buf_size` - Buffer size when reading corpus.
-/// - `char_include_list` - Slice of non-Thai character to be vectorized.
-/// - `corpuses` - Slice of PathBuf that point to corpus file.
-/// - `init` - An RwLock that store u8. An unsign int value that will be used
-/// on char that has no map inside `map` table yet.
-/// - `map` - A HashMap that map a character to unsign int.
+/// Number of most repeated sentences printed by --sentence-dupes.
+const SENTENCE_DUPES_SHOWN: usize = 10;
+
+/// Ask user on stdin whether existing output file may be overwritten.
 /// 
 /// # Return
-/// Vec contains a pair of u8. The first u8 is vectorized char. The second u8 is tag.
-fn vectorize(buf_size: usize, char_include_list: &[char], corpuses: &[PathBuf], init: &RwLock<u8>, map: &RwLock<HashMap<char, u8>>) -> Vec<(u8, u8)> {
-    corpuses.par_iter().flat_map(|f| {
-        println!("Parsing:{}", f.display());
-        let corpus_file = BufReader::with_capacity(buf_size,File::open(f).unwrap());
-        let json: Corpus = from_reader(corpus_file).unwrap();
-        let tagged_file: Vec<(u8, u8)> = json.iter().flat_map(|doc| {
-            let tagged_doc: Vec<(u8, u8)> = doc.iter().flat_map(|sentence| {
-                let tagged_sentence: Vec<(u8, u8)> = sentence.iter().flat_map(|(word, tag)| {
-                    let mut tagged_chars: Vec<(u8, u8)> = word.iter().map(|ch| {
-                        let codepoint = *ch as u32;
-                        if (codepoint < 0x0E01 || codepoint > 0x0E7F) && !char_include_list.contains(ch) {
-                            return (0, 0)
-                        }
+/// true if user answer y or yes.
+fn confirm_overwrite() -> bool {
+    eprintln!("The file to store output already exist. Do you want to overwrite it (y/n) ?");
+    let mut confirm = String::new();
+
+    match std::io::stdin().read_line(&mut confirm) {
+        Ok(_) => ["y", "yes"].contains(&confirm.trim().to_lowercase().as_str()),
+        Err(err) => panic!("{:?}", err)
+    }
+}
+
+/// Print `err` to stderr then exit the process with its exit code.
+fn exit_with(err: RunError) -> ! {
+    eprintln!("{}", err);
+    std::process::exit(err.exit_code())
+}
 
-                        {
-                            let map = map.read().unwrap();
+/// Exit the process with `RunError::Io` if writing `what` into `path` failed.
+fn exit_on_write_error<E: std::fmt::Display>(result: Result<(), E>, what: &str, path: &Path) {
+    if let Err(err) = result {
+        exit_with(RunError::Io(format!("Fail to write {} to {}: {}", what, path.display(), err)));
+    }
+}
 
-                            if let Some(v) = map.get(ch) {
-                                return (*v, 0)
-                            }
-                        }
-                        let mut map = map.write().unwrap();
-                        let mut v = init.write().unwrap();
-                        map.insert(*ch, *v);
+/// Read lines of `path` given to `option` with their line number which start from 1.
+/// It exit the process if the file cannot be opened or read.
+fn numbered_lines<'a>(path: &'a str, option: &'a str) -> impl Iterator<Item = (usize, String)> + 'a {
+    let file = File::open(path).unwrap_or_else(|err| exit_with(RunError::Fatal(format!("Fail to open {} {}: {}", option, path, err))));
+    BufReader::new(file).lines().enumerate().map(move |(i, line)| {
+        let line = line.unwrap_or_else(|err| exit_with(RunError::Fatal(format!("Fail to read {} {} at line {}: {}", option, path, i + 1, err))));
+        (i + 1, line)
+    })
+}
 
-                        *v += 1;
+/// Create a progress bar of `len` files. It is hidden if `quiet` is true.
+fn file_progress(len: usize, quiet: bool) -> ProgressBar {
+    if quiet {
+        return ProgressBar::hidden()
+    }
+    let progress = ProgressBar::new(len as u64);
+    progress.set_style(ProgressStyle::with_template("[{elapsed_precise}] {bar:40} {pos}/{len} files").unwrap());
 
-                        return (*v - 1, 0)
-                    }).collect();
-                    if let Some((_, ref mut l)) = tagged_chars.last_mut() {
-                        *l = *tag;   
-                    }
-                    tagged_chars
-                }).collect();
+    progress
+}
 
-                tagged_sentence
-            }).collect();
 
-            tagged_doc
-        }).collect();
+/// Create a progress of `len` grams being counted. It is hidden if `quiet` is true.
+fn gram_progress(len: usize, quiet: bool) -> ProgressBar {
+    if quiet {
+        return ProgressBar::hidden()
+    }
+    let progress = ProgressBar::new(len as u64);
+    progress.set_style(ProgressStyle::with_template("[{elapsed_precise}] {spinner} {pos}/{len} grams counted").unwrap());
 
-        tagged_file
-    }).collect()
+    progress
 }
 
-fn get_unique_vecs_idx(gram: u8, raw: &[u8]) -> Vec<usize> {
-    let g = gram as usize;
-    let len = raw.len() - g;
-    let mut flatten : Vec<Vec<u8>> = (0..len).into_par_iter().map(|i| {
-        (0..g).into_iter().map(|j| raw[i + j]).collect()
-    }).collect();
-    flatten.sort_unstable();
-    
-    let unique = vec![0];
-    let flatten_1 = &flatten[1..];
-    let flatten_0 = &flatten[..(flatten.len() - 1)];
-    let matcher = flatten_0.iter().zip(flatten_1.iter());
-    let unique: Vec<usize> = unique.into_iter()
-            .chain(
-                matcher.enumerate()
-                    .filter_map(|(i, (f_0, f_1))| {
-                        if f_0.eq(f_1) {
-                            None
-                        } else {
-                            Some(i + 1)
-                        }
-                    }
-                )
-            ).collect();
 
-    // unique.append(&mut remain);
+/// Create a spinner with `message`. It is hidden if `quiet` is true.
+fn spinner(message: &'static str, quiet: bool) -> ProgressBar {
+    if quiet {
+        return ProgressBar::hidden()
+    }
+    let spinner = ProgressBar::new_spinner();
+    spinner.set_message(message);
+    spinner.enable_steady_tick(Duration::from_millis(100));
 
-    unique
+    spinner
 }
 
+
 fn main() {
     let matches = App::new("BEST corpus analysis")
                     .version("0.0.1")
                     .author("Nattapong Sirilappanich")
                     .about("Analyze BEST corpus by various factor specified in paramter")
+                    .after_help(
+"EXIT CODES:
+    0    Analysis completed
+    1    Analysis is aborted by fatal error
+    2    Some corpus files are skipped since they cannot be opened or parsed,
+         or an output file cannot be written
+    3    No n-gram is produced from the corpus
+    130  Analysis is interrupted by Ctrl-C. Only completed output is stored")
                     .arg(Arg::with_name("gram")
                                 .short("g")
                                 .long("gram")
                                 .value_name("NUMBER")
                                 .takes_value(true)
-                                .required(true)
-                                .help("Number of gram to be analyzed. For example, 3")
+                                .multiple(true)
+                                .required_unless("no ngram")
+                                .help("Number of gram to be analyzed. For example, 3. Multiple grams can be given, e.g. -g 2 3")
                                 .validator(|n| {
-                                    let n = n.parse::<u8>().unwrap();
-                                    if n == 0 {
-                                        Err("NUMBER must be greater than 0".to_string())
-                                    } else {
-                                        Ok(())
+                                    match n.parse::<u8>() {
+                                        Ok(0) => Err("NUMBER must be greater than 0".to_string()),
+                                        Ok(_) => Ok(()),
+                                        Err(_) => Err(format!("NUMBER must be a number in range 1..255 but found \"{}\"", n))
                                     }
                                 }))
+                    .arg(Arg::with_name("no ngram")
+                                .long("no-ngram")
+                                .conflicts_with("gram")
+                                .help("Only vectorize corpus and store char map, character frequencies, and other corpus statistics without n-gram analysis"))
                     .arg(Arg::with_name("corpus src")
                                 .short("s")
                                 .long("src")
                                 .value_name("FILES")
                                 .multiple(false)
                                 .takes_value(true)
-                                .required(true)
+                                .required_unless("src list")
                                 .min_values(1)
                                 .help("Files storing corpus.")
                                 .long_help(
 "
-Files that contains corpus. You may use glob style file path if
+Files that contains corpus. Use - to read corpus from standard input.
+You may use glob style file path if
 your path doesn't contain platform specific symbol or environment
 variable. For example, it's OK on following cases:
     -s /home/john/**/corpus source/**/* ../another/dir/*.txt
@@ -159,6 +147,55 @@ Quote make these path a string and delegate path resolve to app.
 However, Rust glob cannot resolve OS dependent glob path.
 Without quote, OS shell will resolve glob for the app.
 If path is platform independent, it doesn't matter if there's any quote or not.
+"
+                                ))
+                    .arg(Arg::with_name("src list")
+                                .long("src-list")
+                                .value_name("FILE")
+                                .takes_value(true)
+                                .help("A file that list corpus files, one path or glob per line. They are added to files given by -s")
+                                .long_help(
+"
+A text file that list corpus files, one path or glob per line.
+Relative path is resolved against current directory.
+Blank line and line that start with # are ignored.
+Files listed are added to files given by -s.
+"
+                                ))
+                    .arg(Arg::with_name("encoding")
+                                .long("encoding")
+                                .value_name("ENCODING")
+                                .default_value("utf8")
+                                .possible_values(&["utf8", "utf16le", "utf16be", "tis620"])
+                                .takes_value(true)
+                                .help("Text encoding of corpus files. Corpus is transcoded to UTF-8 as it is read"))
+                    .arg(Arg::with_name("input format")
+                                .long("input-format")
+                                .value_name("FORMAT")
+                                .default_value("json")
+                                .possible_values(&["json", "best-text"])
+                                .takes_value(true)
+                                .help("Format of corpus files")
+                                .long_help(
+"
+Format of corpus files.
+    json - Nested array of document, sentence, word and character.
+    best-text - Original BEST text. Each line is a sentence and each word end with |.
+        Word in <NE>, <AB> and <POEM> is tagged 2, 3 and 4 respectively. Other word is tagged 1.
+"
+                                ))
+                    .arg(Arg::with_name("test src")
+                                .long("test-src")
+                                .value_name("FILES")
+                                .takes_value(true)
+                                .min_values(1)
+                                .help("Test corpus files. Perplexity of test corpus under n-gram model of source corpus is reported.")
+                                .long_help(
+"
+Test corpus files in the same format and glob style as --src.
+After n-gram model is built from source corpus, perplexity of test corpus under
+the model with add-one smoothing is reported for each gram.
+Test corpus is vectorized with the same character map as source corpus.
 "
                                 ))
                     .arg(Arg::with_name("output file")
@@ -167,37 +204,65 @@ If path is platform independent, it doesn't matter if there's any quote or not.
                                 .value_name("FILE")
                                 .default_value("out.csv")
                                 .takes_value(true)
-                                .help("CSV file to store analyze result")
-                                .validator(|path| {
-                                    // validate if given output path already exist
-                                    // and confirm user if it's ok to overwrite it.
-                                    if Path::new(&path).exists() {
-                                        println!("The file to store output already exist. Do you want to overwrite it (y/n) ?");
-                                        let mut confirm = String::new();
-                                        
-                                        match std::io::stdin().read_line(&mut confirm) {
-                                            Ok(_) => {
-                                                let confirm = confirm.trim().to_lowercase();
-                                                
-                                                if !["y", "yes"].contains(&confirm.as_str()) {
-                                                    return Err("The destination to store analyzed data already exist".to_owned())
-                                                }
-                                            },
-                                            Err(err) => {
-                                                panic!("{:?}", err)
-                                            }
-                                        }
-                                    }
-
-                                    Ok(())
-                                }))
+                                .help("CSV file to store analyze result. If it already exist, you are asked to confirm overwriting it unless --append is used"))
+                    .arg(Arg::with_name("append")
+                                .long("append")
+                                .help("Append n-gram frequency table to existing output file instead of overwriting it. Header is only written to empty file. Only CSV format is supported"))
+                    .arg(Arg::with_name("format")
+                                .long("format")
+                                .value_name("FORMAT")
+                                .default_value("csv")
+                                .possible_values(&["csv", "json"])
+                                .takes_value(true)
+                                .help("Format of analysis result"))
+                    .arg(Arg::with_name("smoothing")
+                                .long("smoothing")
+                                .value_name("SMOOTHING")
+                                .default_value("none")
+                                .possible_values(&["none", "good-turing"])
+                                .takes_value(true)
+                                .help("Add probability column of each n-gram smoothed by given method to n-gram frequency table")
+                                .long_help(
+"
+Add probability column of each n-gram to n-gram frequency table.
+    none - No probability column.
+    good-turing - Simple Good-Turing estimate. Probability of unseen n-grams,
+        which is the number of n-grams that occur once divided by total occurrences,
+        is reserved and reported. Probability is estimated from kept n-grams so
+        it is approximate when --max-ngrams is used.
+"
+                                ))
+                    .arg(Arg::with_name("out dir")
+                                .long("out-dir")
+                                .value_name("DIR")
+                                .takes_value(true)
+                                .help("Directory to store every analysis result in conventionally named files")
+                                .long_help(
+"
+Directory to store every analysis result in conventionally named files.
+It is created if it doesn't exist. Files written are:
+    ngram_<gram>.csv - N-gram frequency table of each gram
+    coverage_<gram>.csv - Coverage table of each gram when --coverage is used
+    fof_<gram>.csv - Frequency of frequencies of each gram
+    ngram_<gram>.intra_word.csv, ngram_<gram>.inter_word.csv - When --boundary-split is used
+    char_freq.csv - Character frequencies
+    char_map.csv - Map of vectorized character id to character
+    word_len.csv - Number of words of each length
+    transitions.csv - Character transitions
+    stats.csv - Statistic of each corpus file
+    report.json - Summary of the whole run
+A file given explicitly by its own option, e.g. --char-freq-out, take precedence.
+If --out is also given, it store n-gram frequency table of the first gram.
+"
+                                ))
                     .arg(Arg::with_name("input buffer")
                                 .short("ib")
                                 .long("input-buffer")
                                 .value_name("BUFFER_SIZE")
                                 .default_value("16M")
                                 .takes_value(true)
-                                .help("Buffer size in bytes for corpus file reader. Default is 16MB."))
+                                .help("Buffer size in bytes for corpus file reader. Default is 16MB.")
+                                .validator(|b| parse_buffer_size(&b).map(|_| ())))
                     .arg(Arg::with_name("non-thai chars")
                                 .short("cl")
                                 .long("char-list-file")
@@ -208,22 +273,445 @@ If path is platform independent, it doesn't matter if there's any quote or not.
 "
 A text file that contains a non-Thai character per line.
 These are characters that will be vectorized into unique
-number. 
+number. A line may also be a range of characters, e.g. a-z,
+or a range of hex codepoints, e.g. 0030-0039.
+Blank lines and comment lines starting with # are ignored.
+"
+                                ))
+                    .arg(Arg::with_name("include chars")
+                                .long("include-chars")
+                                .value_name("STRING")
+                                .takes_value(true)
+                                .help("Non-Thai characters to be vectorized, e.g. \"0123456789\". They are merged with characters from --char-list-file."))
+                    .arg(Arg::with_name("equiv class")
+                                .long("equiv-class")
+                                .value_name("CHARS=NAME")
+                                .takes_value(true)
+                                .multiple(true)
+                                .number_of_values(1)
+                                .validator(|c| parse_equiv_class(&c).map(|_| ()))
+                                .help("Characters that share one vectorized char, e.g. \"0123456789=DIGIT\". It can be given multiple times")
+                                .long_help(
+"
+Every character in CHARS is vectorized as the same vectorized char so n-gram
+analysis treat them identically. The class is named NAME in char map and
+its first character represent the class in n-gram tables.
+Characters in a class are always vectorized even if they are not in --unicode-range.
+It can be given multiple times, e.g.
+--equiv-class \"0123456789=DIGIT\" --equiv-class \"๐๑๒๓๔๕๖๗๘๙=THAI_DIGIT\"
+A character can belong to only one class.
+"))
+                    .arg(Arg::with_name("unicode range")
+                                .long("unicode-range")
+                                .value_name("START..END")
+                                .default_value("0E01..0E7F")
+                                .takes_value(true)
+                                .help("Comma separated hex unicode ranges of characters to be vectorized. Default is Thai range.")
+                                .validator(|r| parse_unicode_ranges(&r).map(|_| ())))
+                    .arg(Arg::with_name("oov mode")
+                                .long("oov-mode")
+                                .value_name("MODE")
+                                .default_value("keep-position")
+                                .possible_values(&["drop", "token", "keep-position"])
+                                .takes_value(true)
+                                .help("How to vectorize characters that are neither in unicode range nor include list")
+                                .long_help(
+"
+How to vectorize characters that are neither in unicode range nor include list.
+    drop - Remove them so n-gram is formed as if they never exist.
+    token - Vectorize all of them to a single reserved id.
+    keep-position - Vectorize all of them to 0.
+"
+                                ))
+                    .arg(Arg::with_name("normalize")
+                                .long("normalize")
+                                .value_name("FORM")
+                                .default_value("none")
+                                .possible_values(&["none", "nfc", "nfd"])
+                                .takes_value(true)
+                                .help("Unicode normalization applied to each word before it is vectorized"))
+                    .arg(Arg::with_name("fold case")
+                                .long("fold-case")
+                                .help("Fold case of vectorized characters, e.g. included Latin letters, so upper and lower case share the same id. Thai characters are unaffected"))
+                    .arg(Arg::with_name("id width")
+                                .long("id-width")
+                                .value_name("WIDTH")
+                                .default_value("u32")
+                                .possible_values(&["u8", "u16", "u32"])
+                                .takes_value(true)
+                                .help("Integer width of vectorized character. Analysis abort if corpus has more distinct characters than it can hold."))
+                    .arg(Arg::with_name("tag width")
+                                .long("tag-width")
+                                .value_name("WIDTH")
+                                .default_value("u8")
+                                .possible_values(&["u8", "u16"])
+                                .takes_value(true)
+                                .help("Integer width of tag in corpus. Analysis abort if corpus has tag larger than it can hold."))
+                    .arg(Arg::with_name("only tags")
+                                .long("only-tags")
+                                .value_name("TAGS")
+                                .takes_value(true)
+                                .conflicts_with("exclude tags")
+                                .help("Comma separated tags. Only words with one of these tags are analyzed, e.g. 1,4,7")
+                                .validator(|t| parse_tags(&t).map(|_| ())))
+                    .arg(Arg::with_name("exclude tags")
+                                .long("exclude-tags")
+                                .value_name("TAGS")
+                                .takes_value(true)
+                                .help("Comma separated tags. Words with one of these tags are not analyzed, e.g. 1,4,7")
+                                .validator(|t| parse_tags(&t).map(|_| ())))
+                    .arg(Arg::with_name("tag map")
+                                .long("tag-map")
+                                .value_name("FILE")
+                                .takes_value(true)
+                                .help("A file that map tag in corpus to another tag, one old=new per line")
+                                .long_help(
+"
+A text file that map tag in corpus to another tag before
+analysis, one old=new per line, e.g. 5=4. Several tags can be
+mapped to the same tag to merge them into one class.
+Tags that are not in the file are kept as is. --only-tags and
+--exclude-tags apply to mapped tags.
+Blank lines and comment lines starting with # are ignored."))
+                    .arg(Arg::with_name("sample")
+                                .long("sample")
+                                .value_name("FRACTION")
+                                .takes_value(true)
+                                .help("Only analyze a deterministic random fraction of documents, e.g. 0.1, for a quick approximate run")
+                                .validator(|f| parse_sample_fraction(&f).map(|_| ())))
+                    .arg(Arg::with_name("seed")
+                                .long("seed")
+                                .value_name("NUMBER")
+                                .default_value("0")
+                                .takes_value(true)
+                                .help("Seed that decide which documents are sampled by --sample")
+                                .validator(|s| {
+                                    s.parse::<u64>().map(|_| ()).map_err(|_| "Seed must be a non-negative integer".to_string())
+                                }))
+                    .arg(Arg::with_name("doc range")
+                                .long("doc-range")
+                                .value_name("START:END")
+                                .takes_value(true)
+                                .help("Only analyze documents whose index, counted across every corpus file in order, is in half-open range START:END")
+                                .validator(|r| parse_doc_range(&r).map(|_| ())))
+                    .arg(Arg::with_name("boundaries")
+                                .long("boundaries")
+                                .value_name("MODE")
+                                .possible_values(&["ignore", "sentence", "document"])
+                                .takes_value(true)
+                                .help("Boundaries that n-gram must not cross. Default is ignore which flatten whole corpus into one sequence")
+                                .long_help("
+Boundaries that n-gram must not cross.
+ignore - flatten whole corpus into one sequence so n-gram may cross sentence and document. This is the default.
+sentence - only analyze n-gram that fully lie inside a single sentence.
+document - only analyze n-gram that fully lie inside a single document."))
+                    .arg(Arg::with_name("respect boundaries")
+                                .long("respect-boundaries")
+                                .conflicts_with("boundaries")
+                                .help("Only analyze n-gram that fully lie inside a single sentence. Same as --boundaries sentence"))
+                    .arg(Arg::with_name("with tags")
+                                .long("with-tags")
+                                .help("Include tag of each character in n-gram so n-grams with different word boundaries are distinct"))
+                    .arg(Arg::with_name("validate only")
+                                .long("validate-only")
+                                .help("Only parse and validate corpus without running analysis"))
+                    .arg(Arg::with_name("strict")
+                                .long("strict")
+                                .help("Abort when any corpus file cannot be opened or parsed instead of skipping it with a warning"))
+                    .arg(Arg::with_name("dry run")
+                                .long("dry-run")
+                                .conflicts_with("validate only")
+                                .help("Only expand corpus paths and parse first document of each file without running analysis. No file is written."))
+                    .arg(Arg::with_name("boundary split")
+                                .long("boundary-split")
+                                .help("Also count n-gram that lie inside a word separately from n-gram that span word boundary"))
+                    .arg(Arg::with_name("positional")
+                                .long("positional")
+                                .help("Also count n-gram at start, middle, and end of sentence separately and store each table next to the aggregate, suffixed by its position"))
+                    .arg(Arg::with_name("per file")
+                                .long("per-file")
+                                .help("Also analyze each corpus file separately and store its n-gram table next to the aggregate, suffixed by its file name"))
+                    .arg(Arg::with_name("longest repeat")
+                                .long("longest-repeat")
+                                .help("Also report the longest character sequence that occur more than once in corpus"))
+                    .arg(Arg::with_name("find dupes")
+                                .long("find-dupes")
+                                .help("Also report groups of documents that have identical vectorized characters along with their file and index"))
+                    .arg(Arg::with_name("sentence dupes")
+                                .long("sentence-dupes")
+                                .help("Also report the most frequently repeated sentences along with their number of occurrence"))
+                    .arg(Arg::with_name("drop sentence dupes")
+                                .long("drop-sentence-dupes")
+                                .help("Analyze only the first occurrence of each repeated sentence. Repeated sentences are also reported as in --sentence-dupes"))
+                    .arg(Arg::with_name("verify")
+                                .long("verify")
+                                .hidden(true)
+                                .help("Recount unique n-grams with brute force HashSet and abort if it doesn't match"))
+                    .arg(Arg::with_name("quiet")
+                                .short("q")
+                                .long("quiet")
+                                .conflicts_with("verbose")
+                                .help("Do not show progress and only log errors"))
+                    .arg(Arg::with_name("verbose")
+                                .short("v")
+                                .long("verbose")
+                                .multiple(true)
+                                .help("Log more diagnostics to stderr. -v also log each corpus file and time of each stage, -vv log everything")
+                                .long_help(
+"
+Results, e.g. totals, entropy and top n-grams, are printed to stdout while
+diagnostics, e.g. settings, warnings and paths of stored files, are logged to stderr.
+Without this flag, info and more severe diagnostics are logged.
+-v also log debug diagnostics, e.g. each corpus file being parsed and time of each stage.
+-vv log everything. RUST_LOG environment variable, e.g. RUST_LOG=warn, override this flag.
+"
+                                ))
+                    .arg(Arg::with_name("stride")
+                                .long("stride")
+                                .value_name("NUMBER")
+                                .default_value("1")
+                                .takes_value(true)
+                                .help("Distance between start of two consecutive n-grams. Use number of gram for non-overlapping n-grams.")
+                                .validator(|n| {
+                                    match n.parse::<usize>() {
+                                        Ok(n) if n >= 1 => Ok(()),
+                                        _ => Err("NUMBER must be greater than 0".to_string())
+                                    }
+                                }))
+                    .arg(Arg::with_name("threads")
+                                .short("t")
+                                .long("threads")
+                                .value_name("NUMBER")
+                                .default_value("0")
+                                .takes_value(true)
+                                .help("Number of threads used for analysis. 0 means use all cores.")
+                                .validator(|n| {
+                                    n.parse::<usize>().map(|_| ()).map_err(|_| "NUMBER must be a non-negative integer".to_string())
+                                }))
+                    .arg(Arg::with_name("top")
+                                .long("top")
+                                .value_name("K")
+                                .takes_value(true)
+                                .help("Print K most frequent n-grams")
+                                .validator(|k| {
+                                    k.parse::<usize>().map(|_| ()).map_err(|_| "K must be a non-negative integer".to_string())
+                                }))
+                    .arg(Arg::with_name("min count")
+                                .long("min-count")
+                                .value_name("N")
+                                .default_value("1")
+                                .takes_value(true)
+                                .help("Exclude n-grams that occur less than N times from frequency table and top-K report. Entropy and coverage still use every n-gram.")
+                                .validator(|n| {
+                                    n.parse::<usize>().map(|_| ()).map_err(|_| "N must be a non-negative integer".to_string())
+                                }))
+                    .arg(Arg::with_name("max ngrams")
+                                .long("max-ngrams")
+                                .value_name("N")
+                                .takes_value(true)
+                                .help("Keep only N most frequent n-grams while counting to bound memory. Unique count still reflect every n-gram.")
+                                .validator(|n| {
+                                    match n.parse::<usize>() {
+                                        Ok(n) if n > 0 => Ok(()),
+                                        _ => Err("N must be a positive integer".to_string())
+                                    }
+                                }))
+                    .arg(Arg::with_name("coverage")
+                                .long("coverage")
+                                .value_name("THRESHOLDS")
+                                .takes_value(true)
+                                .help("Comma separated cumulative coverage, e.g. 0.5,0.9,0.99, to report how many most frequent n-grams cover it")
+                                .validator(|t| parse_coverage_thresholds(&t).map(|_| ())))
+                    .arg(Arg::with_name("transitions out")
+                                .long("transitions-out")
+                                .value_name("FILE")
+                                .takes_value(true)
+                                .help("CSV file to store transition count between consecutive characters"))
+                    .arg(Arg::with_name("with chars")
+                                .long("with-chars")
+                                .help("Add column of decoded characters to CSV n-gram frequency table"))
+                    .arg(Arg::with_name("delimiter")
+                                .long("delimiter")
+                                .value_name("CHAR")
+                                .default_value(",")
+                                .takes_value(true)
+                                .help("Field delimiter of CSV n-gram frequency table. Use \\t for tab. Fields containing delimiter, quote, or newline are quoted")
+                                .validator(|d| parse_delimiter(&d).map(|_| ())))
+                    .arg(Arg::with_name("document frequency")
+                                .long("document-frequency")
+                                .help("Add column of number of documents that each n-gram occur in to n-gram frequency table"))
+                    .arg(Arg::with_name("shard by prefix")
+                                .long("shard-by-prefix")
+                                .help("Split n-gram frequency table into a file per first character id, e.g. out.prefix_3.csv"))
+                    .arg(Arg::with_name("map out")
+                                .long("map-out")
+                                .value_name("FILE")
+                                .takes_value(true)
+                                .help("CSV file to store map of vectorized character id to character"))
+                    .arg(Arg::with_name("report")
+                                .long("report")
+                                .value_name("FILE")
+                                .takes_value(true)
+                                .help("JSON file to store summary of corpus statistic and analysis of each gram"))
+                    .arg(Arg::with_name("checkpoint")
+                                .long("checkpoint")
+                                .value_name("FILE")
+                                .takes_value(true)
+                                .help("File to persist vectorization progress after each corpus file so an interrupted run can resume")
+                                .long_help(
+"
+File to persist vectorization progress. A record is appended to it each time
+a corpus file is fully vectorized. If the file exist, its character map is restored
+and corpus files already recorded in it are not parsed again. Corpus files are
+recognized by their canonical path so the run can be resumed from any directory.
+The file should only be reused with the same vectorization options.
+It cannot be used when corpus is read from standard input.
 "
                                 ))
+                    .arg(Arg::with_name("word len out")
+                                .long("word-len-out")
+                                .value_name("FILE")
+                                .takes_value(true)
+                                .help("CSV file to store number of words of each length in vectorized characters"))
+                    .arg(Arg::with_name("vec out")
+                                .long("vec-out")
+                                .value_name("FILE")
+                                .takes_value(true)
+                                .help("Binary file to store vectorized corpus and its tags, each packed in the minimal byte width recorded in its header"))
+                    .arg(Arg::with_name("stats out")
+                                .long("stats-out")
+                                .value_name("FILE")
+                                .takes_value(true)
+                                .help("CSV file to store statistic of each corpus file"))
+                    .arg(Arg::with_name("char freq out")
+                                .long("char-freq-out")
+                                .value_name("FILE")
+                                .takes_value(true)
+                                .help("CSV file to store frequency of each character"))
+                    .arg(Arg::with_name("fof out")
+                                .long("fof-out")
+                                .value_name("FILE")
+                                .takes_value(true)
+                                .help("CSV file to store frequency of frequencies, i.e. number of n-grams that occur exactly each number of times. It is suffixed by gram when multiple grams are analyzed"))
                     .get_matches();
-    let gram = matches.value_of("gram").unwrap().parse::<u8>().unwrap();
-    let sources = matches.values_of("corpus src").unwrap();
+    let level = match (matches.is_present("quiet"), matches.occurrences_of("verbose")) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        _ => LevelFilter::Trace
+    };
+    // diagnostics go to stderr so stdout only carry results.
+    // Only warnings and errors are decorated by their level
+    env_logger::Builder::new()
+        .filter_level(level)
+        .format(|buf, record| match record.level() {
+            Level::Error | Level::Warn => writeln!(buf, "{}: {}", record.level(), record.args()),
+            _ => writeln!(buf, "{}", record.args())
+        })
+        .parse_default_env()
+        .init();
+    let grams: Vec<u8> = matches.values_of("gram").map(|g| g.map(|g| g.parse::<u8>().unwrap()).collect()).unwrap_or_default();
+    let mut sources: Vec<String> = matches.values_of("corpus src").map(|s| s.map(String::from).collect()).unwrap_or_default();
+    if let Some(path) = matches.value_of("src list") {
+        sources.extend(numbered_lines(path, "src-list-file").map(|(_, line)| line.trim().to_owned()).filter(|line| {
+            !line.is_empty() && !line.starts_with('#')
+        }));
+    }
     let out_path = matches.value_of("output file").unwrap();
-    let input_buffer_size = unbytify(matches.value_of("input buffer").unwrap()).unwrap() as usize;
+    let format = matches.value_of("format").unwrap().parse::<OutputFormat>().unwrap();
+    let append = matches.is_present("append");
+    if append && format != OutputFormat::Csv {
+        exit_with(RunError::Fatal("--append only support CSV format".to_owned()));
+    }
+    let validate_only = matches.is_present("validate only");
+    let dry_run = matches.is_present("dry run");
+    let no_ngram = matches.is_present("no ngram");
+    // appended output file is never overwritten and nothing is written to it when only validating,
+    // in dry run, or without n-gram analysis so there is nothing to confirm
+    if !append && !validate_only && !dry_run && !no_ngram && Path::new(out_path).exists() && !confirm_overwrite() {
+        exit_with(RunError::Fatal("The destination to store analyzed data already exist".to_owned()));
+    }
+    let mut input_buffer_size = parse_buffer_size(matches.value_of("input buffer").unwrap()).unwrap();
+    if input_buffer_size > INPUT_BUFFER_MAX {
+        warn!("Input buffer {} is clamped to {}", format_bytes(input_buffer_size), format_bytes(INPUT_BUFFER_MAX));
+        input_buffer_size = INPUT_BUFFER_MAX;
+    } else if input_buffer_size > INPUT_BUFFER_WARN {
+        warn!("Input buffer {} is allocated for each corpus file being read", format_bytes(input_buffer_size));
+    }
+    let unicode_ranges = parse_unicode_ranges(matches.value_of("unicode range").unwrap()).unwrap();
+    let oov_mode = matches.value_of("oov mode").unwrap().parse::<OovMode>().unwrap();
+    let input_format = matches.value_of("input format").unwrap().parse::<InputFormat>().unwrap();
+    let encoding = matches.value_of("encoding").unwrap().parse::<Encoding>().unwrap();
+    let read_option = ReadOption {buf_size: input_buffer_size, format: input_format, encoding, strict: matches.is_present("strict")};
+    let normalization = matches.value_of("normalize").unwrap().parse::<Normalization>().unwrap();
+    let id_width = matches.value_of("id width").unwrap().parse::<IdWidth>().unwrap();
+    let tag_width = matches.value_of("tag width").unwrap().parse::<TagWidth>().unwrap();
+    let tag_filter = match (matches.value_of("only tags"), matches.value_of("exclude tags")) {
+        (Some(tags), _) => TagFilter::Only(parse_tags(tags).unwrap()),
+        (_, Some(tags)) => TagFilter::Exclude(parse_tags(tags).unwrap()),
+        _ => TagFilter::All
+    };
+    let tag_map = match matches.value_of("tag map") {
+        Some(path) => {
+            numbered_lines(path, "tag-map-file").filter_map(|(line_no, line)| {
+                parse_tag_map_line(&line).unwrap_or_else(|err| {
+                    exit_with(RunError::Fatal(format!("Invalid tag-map-file {} at line {}: {}", path, line_no, err)))
+                })
+            }).collect()
+        },
+        None => HashMap::new()
+    };
+    if let Some(tag) = tag_map.values().find(|tag| **tag > tag_width.max()) {
+        exit_with(RunError::Fatal(format!("Tag {} in tag-map-file exceed {} tag width", tag, tag_width)));
+    }
+    let sample = matches.value_of("sample").map(|f| Sample {
+        fraction: parse_sample_fraction(f).unwrap(),
+        seed: matches.value_of("seed").unwrap().parse::<u64>().unwrap()
+    });
+    let boundaries = match matches.value_of("boundaries") {
+        Some(mode) => mode.parse::<Boundaries>().unwrap(),
+        None if matches.is_present("respect boundaries") => Boundaries::Sentence,
+        None => Boundaries::Ignore
+    };
+    let with_tags = matches.is_present("with tags");
+    let boundary_split = matches.is_present("boundary split");
+    let positional = matches.is_present("positional");
+    let per_file = matches.is_present("per file");
+    let find_longest_repeat = matches.is_present("longest repeat");
+    let find_dupes = matches.is_present("find dupes");
+    let drop_sentence_dupes = matches.is_present("drop sentence dupes");
+    let sentence_dupes = drop_sentence_dupes || matches.is_present("sentence dupes");
+    let stride = matches.value_of("stride").unwrap().parse::<usize>().unwrap();
+    let quiet = matches.is_present("quiet");
+    let threads = matches.value_of("threads").unwrap().parse::<usize>().unwrap();
+    let out_dir = matches.value_of("out dir").map(PathBuf::from);
+    if let Some(dir) = out_dir.as_ref().filter(|_| !dry_run) {
+        if let Err(err) = std::fs::create_dir_all(dir) {
+            exit_with(RunError::Io(format!("Fail to create output directory {}: {}", dir.display(), err)));
+        }
+    }
+    // explicit path take precedence over conventional file name in out dir
+    let artifact_path = |explicit: Option<&str>, name: &str| {
+        explicit.map(PathBuf::from).or_else(|| out_dir.as_ref().map(|dir| dir.join(name)))
+    };
+    let char_freq_path = artifact_path(matches.value_of("char freq out"), "char_freq.csv");
+    let coverage_thresholds = matches.value_of("coverage").map(|t| parse_coverage_thresholds(t).unwrap()).unwrap_or_default();
+    let vec_path = matches.value_of("vec out").map(PathBuf::from);
+    let transitions_path = artifact_path(matches.value_of("transitions out"), "transitions.csv");
+    let stats_path = artifact_path(matches.value_of("stats out"), "stats.csv");
+    let checkpoint_path = matches.value_of("checkpoint");
+    let report_path = artifact_path(matches.value_of("report"), "report.json");
+    let map_path = artifact_path(matches.value_of("map out"), "char_map.csv");
+    let word_len_path = artifact_path(matches.value_of("word len out"), "word_len.csv");
+    let top = matches.value_of("top").map(|k| k.parse::<usize>().unwrap());
+    let min_count = matches.value_of("min count").unwrap().parse::<usize>().unwrap();
+    let max_ngrams = matches.value_of("max ngrams").map(|n| n.parse::<usize>().unwrap());
     let mut char_include_list = match matches.value_of("non-thai chars") {
         Some(path) => {
-            let reader = BufReader::new(File::open(path).expect("Invalid char-list-file path"));
-            reader.lines().into_iter().map(|line| {
-                let l = line.unwrap();
-                // The only case of unwrap fail is empty line
-                // We treat it as user expected to have \n added.
-                l.chars().next().unwrap_or('\n')
+            numbered_lines(path, "char-list-file").flat_map(|(line_no, line)| {
+                parse_char_list_line(&line).unwrap_or_else(|err| {
+                    exit_with(RunError::Fatal(format!("Invalid char-list-file {} at line {}: {}", path, line_no, err)))
+                })
             }).collect()
         },
         None => {
@@ -231,33 +719,357 @@ number.
         }
     };
 
+    if let Some(chars) = matches.value_of("include chars") {
+        char_include_list.extend(chars.chars());
+    }
     char_include_list.sort_unstable();
     char_include_list.dedup();
+    let equiv_classes = matches.values_of("equiv class").map(|classes| {
+        classes.map(|c| parse_equiv_class(c).unwrap()).collect()
+    }).unwrap_or_default();
+    let equiv_classes = EquivClasses::new(equiv_classes).unwrap_or_else(|err| exit_with(RunError::Fatal(err)));
 
-    println!("{}-gram", gram);
-    println!("Total {} source files", sources.len());
-    println!("Input buffer: {} bytes", input_buffer_size);
-    println!("Total non-Thai characters to be included is {} chars", char_include_list.len());
-    // glob all the path specified by user
-    let corpuses = sources.map(|s| {
-        glob(s).unwrap().map(|g| g.unwrap())
-    }).flatten().collect::<Vec<PathBuf>>();
-    println!("Store output to {}", out_path);
+    if grams.is_empty() {
+        info!("N-gram analysis is skipped");
+    } else {
+        info!("{}-gram", grams.iter().map(|g| g.to_string()).collect::<Vec<String>>().join(", "));
+    }
+    info!("Total {} source files", sources.len());
+    info!("Input buffer: {} bytes", input_buffer_size);
+    info!("Unicode ranges: {}", unicode_ranges.iter().map(|r| format!("{:04X}..{:04X}", r.start(), r.end())).collect::<Vec<String>>().join(","));
+    info!("Total non-Thai characters to be included is {} chars", char_include_list.len());
+    info!("Excluded characters are vectorized by {:?} mode", oov_mode);
+    info!("Unicode normalization: {:?}", normalization);
+    if matches.is_present("fold case") {
+        info!("Case of vectorized characters is folded");
+    }
+    info!("Vectorized character width: {}", id_width);
+    info!("Tag width: {}", tag_width);
+    match &tag_filter {
+        TagFilter::Only(tags) => info!("Only words with tags {:?} are analyzed", tags),
+        TagFilter::Exclude(tags) => info!("Words with tags {:?} are not analyzed", tags),
+        TagFilter::All => ()
+    }
+    if boundaries != Boundaries::Ignore {
+        info!("N-gram will not cross {} boundaries", boundaries);
+    }
+    if let Some(sample) = sample {
+        info!("Sample {}% of documents with seed {}", sample.fraction * 100f64, sample.seed);
+    }
+    // 0 thread let rayon decide number of threads which is equals to number of cores
+    rayon::ThreadPoolBuilder::new().num_threads(threads).build_global().unwrap();
+    // first Ctrl-C let completed work be stored, second one abort immediately
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let handler_interrupted = Arc::clone(&interrupted);
+    ctrlc::set_handler(move || {
+        if handler_interrupted.swap(true, Ordering::SeqCst) {
+            std::process::exit(RunError::Interrupted.exit_code());
+        }
+        warn!("Interrupted. Storing completed output, press Ctrl-C again to abort immediately");
+    }).expect("Fail to set Ctrl-C handler");
+    info!("Using {} threads", rayon::current_num_threads());
     let timer = Instant::now();
-    let map = RwLock::new(HashMap::<char, u8>::new());
-    let v = RwLock::new(1u8);
-
-    let tagged_chars: Vec<(u8, u8)> = vectorize(input_buffer_size, &char_include_list, &corpuses, &v, &map);
-
-    println!("Total parsing took {} s", timer.elapsed().as_secs());
+    let corpuses = expand_corpuses(&sources.iter().map(|s| s.as_str()).collect::<Vec<&str>>()).unwrap_or_else(|err| exit_with(RunError::Fatal(err)));
+    let expand_time = timer.elapsed();
+    let test_corpuses = matches.values_of("test src").map(|s| {
+        expand_corpuses(&s.collect::<Vec<&str>>()).unwrap_or_else(|err| exit_with(RunError::Fatal(err)))
+    });
+    if dry_run {
+        let bytes: u64 = corpuses.iter().filter_map(|f| std::fs::metadata(f).ok()).map(|m| m.len()).sum();
+        println!("Total {} corpus files, {}", corpuses.len(), format_bytes(bytes as usize));
+        let failures = peek_corpuses(read_option, &corpuses);
+        failures.iter().for_each(|f| eprintln!("{}", f));
+        if !failures.is_empty() {
+            exit_with(RunError::Fatal(format!("{} corpus files cannot be parsed", failures.len())));
+        }
+        println!("All {} corpus files can be parsed", corpuses.len());
+        return
+    }
+    if validate_only {
+        let violations = validate_corpuses(read_option, &corpuses);
+        violations.iter().for_each(|v| eprintln!("{}", v));
+        if !violations.is_empty() {
+            exit_with(RunError::Fatal(format!("Found {} violations in corpus", violations.len())));
+        }
+        println!("All {} corpus files are valid", corpuses.len());
+        return
+    }
+    let doc_range = matches.value_of("doc range").map(|r| {
+        if corpuses.iter().any(|f| f == Path::new("-")) {
+            exit_with(RunError::Fatal("--doc-range cannot be used with standard input because corpus is read twice".to_owned()));
+        }
+        let document_counts = count_documents(read_option, &corpuses);
+        let documents: usize = document_counts.iter().sum();
+        let range = parse_doc_range(r).unwrap();
+        let clamped = range.start.min(documents)..range.end.min(documents);
+        if clamped != range {
+            warn!("Document range {}:{} exceed {} documents in corpus so it is clamped to {}:{}", range.start, range.end, documents, clamped.start, clamped.end);
+        }
+        info!("Only documents in range {}:{} are analyzed", clamped.start, clamped.end);
+        DocRange::new(clamped, &corpuses, &document_counts)
+    });
+    if !grams.is_empty() {
+        info!("Store output to {}", out_path);
+    }
+    let mut option = VectorizeOption {
+        char_include_list,
+        unicode_ranges,
+        equiv_classes,
+        oov_mode,
+        normalization,
+        fold_case: matches.is_present("fold case"),
+        id_width,
+        tag_width,
+        tag_map,
+        tag_filter,
+        sample,
+        doc_range
+    };
+    let config = AnalyzeConfig {
+        grams: grams.clone(),
+        corpuses: corpuses.clone(),
+        read: read_option,
+        vectorize: option.clone(),
+        boundaries,
+        stride,
+        with_tags,
+        max_ngrams,
+        document_frequency: matches.is_present("document frequency"),
+        drop_repeated_sentences: drop_sentence_dupes,
+        checkpoint: checkpoint_path.map(PathBuf::from),
+        verify: matches.is_present("verify"),
+        progress: file_progress(corpuses.len(), quiet),
+        interrupted: Arc::clone(&interrupted),
+        count_progress: gram_progress(grams.len(), quiet)
+    };
+    let AnalysisResult {char_map, next_id, vectorized, dropped_sentences, ngrams, parse_time} = match analyze(config) {
+        Ok(result) => result,
+        Err(AnalyzeError::Interrupted) => {
+            if let Some(path) = checkpoint_path {
+                info!("Completed corpus files are kept in checkpoint {}", path);
+            }
+            exit_with(RunError::Interrupted)
+        },
+        Err(err) => exit_with(err.into())
+    };
+    let Vectorized {tagged: tagged_chars, sentence_lens, document_lens, document_indices, file_stats, word_lens} = vectorized;
+    // test corpus is vectorized with the same char map so vectorized chars are aligned
+    let map = RwLock::new(char_map);
+    let v = RwLock::new(next_id);
+    debug!("Total parsing took {} s", parse_time.as_secs());
+    print_file_stats(&file_stats);
+    if let Some(path) = stats_path {
+        exit_on_write_error(write_file_stats(&path, &file_stats), "file statistics", &path);
+        info!("Store file statistics to {}", path.display());
+    }
     println!("Total {} characters in corpus", tagged_chars.len());
     println!("Total {} unique characters", *v.read().unwrap());
+    // tag 0 mark a character that is not the last of its word so it is not a word tag
+    println!("Total {} distinct tags", tagged_chars.iter().map(|(_, t)| *t).filter(|t| *t != 0).collect::<HashSet<Tag>>().len());
+    let unique_chars = map.read().unwrap().len();
+    if let Some((mean, median)) = word_len_summary(&word_lens) {
+        println!("Word length mean is {} and median is {} characters", mean, median);
+    }
+    if find_dupes {
+        let groups = duplicate_documents(&tagged_chars, &document_lens, &document_indices, &file_stats);
+        println!("Found {} groups of duplicate documents", groups.len());
+        for group in groups {
+            println!("{}", group.iter().map(|(path, idx)| format!("{}#{}", path.display(), idx)).collect::<Vec<String>>().join(", "));
+        }
+    }
+    if sentence_dupes {
+        let repeated = if drop_sentence_dupes { dropped_sentences } else { repeated_sentences(&tagged_chars, &sentence_lens) };
+        let rev_map: HashMap<Id, char> = map.read().unwrap().iter().map(|(c, v)| (*v, *c)).collect();
+        println!("Found {} repeated sentences", repeated.len());
+        for (ids, count) in repeated.iter().take(SENTENCE_DUPES_SHOWN) {
+            println!("{}\t{}", count, decode_ngram(ids, &rev_map));
+        }
+        if drop_sentence_dupes {
+            info!("Drop {} repeated occurrences of sentences from analysis", repeated.iter().map(|(_, count)| count - 1).sum::<usize>());
+        }
+    }
+    if let Some(path) = word_len_path {
+        exit_on_write_error(write_word_lens(&path, &word_lens), "word lengths", &path);
+        info!("Store word lengths to {}", path.display());
+    }
 
-    let (vecs, labels): (Vec<u8>, Vec<u8>) = tagged_chars.iter().cloned().unzip();
+    if let Some(path) = vec_path {
+        exit_on_write_error(write_vectors(&path, &tagged_chars), "vectorized corpus", &path);
+        info!("Store vectorized corpus to {}", path.display());
+    }
+
+    if let Some(path) = char_freq_path {
+        let freqs = char_frequencies(&tagged_chars);
+        exit_on_write_error(write_char_frequencies(&path, &freqs, &map.read().unwrap()), "character frequencies", &path);
+        info!("Store character frequencies to {}", path.display());
+    }
+
+    // test corpus share char map with source corpus so vectorized chars are aligned
+    // but sample and document range only select documents of source corpus
+    option.sample = None;
+    option.doc_range = None;
+    let test_vectorized = test_corpuses.map(|test_corpuses| {
+        let progress = file_progress(test_corpuses.len(), quiet);
+        let control = VectorizeControl {progress: &progress, interrupted: &interrupted};
+        let vectorized = vectorize(read_option, &option, &test_corpuses, &v, &map, control, None);
+        progress.finish_and_clear();
+        vectorized.unwrap_or_else(|err| exit_with(RunError::Fatal(err)))
+    });
+
+    if let Some(path) = map_path {
+        exit_on_write_error(write_char_map(&path, &map.read().unwrap(), &option.equiv_classes), "char map", &path);
+        info!("Store char map to {}", path.display());
+    }
 
     // n-gram analysis
-    let timer = Instant::now();
-    let unique_idx = get_unique_vecs_idx(gram, &vecs);
-    println!("Total unique analysis time is {}s", timer.elapsed().as_secs());
-    println!("Total {} unique {}-gram", unique_idx.len(), gram);
+    let window = WindowOption {
+        stride,
+        sentence_lens: boundaries.segment_lens(&sentence_lens, &document_lens)
+    };
+    let report_option = ReportOption {
+        top,
+        oov_id: oov_mode.oov_id(id_width),
+        max_ngrams,
+        min_count,
+        coverage: coverage_thresholds,
+        rev_map: map.read().unwrap().iter().map(|(c, v)| (*v, *c)).collect(),
+        out_path: PathBuf::from(out_path),
+        copy_path: None,
+        coverage_path: None,
+        fof_path: None,
+        format,
+        delimiter: parse_delimiter(matches.value_of("delimiter").unwrap()).unwrap(),
+        with_chars: matches.is_present("with chars"),
+        document_lens: if matches.is_present("document frequency") { Some(document_lens.clone()) } else { None },
+        shard_by_prefix: matches.is_present("shard by prefix"),
+        smoothing: matches.value_of("smoothing").unwrap().parse::<Smoothing>().unwrap(),
+        append
+    };
+    let (vecs, labels): (Vec<Id>, Vec<Tag>) = tagged_chars.iter().cloned().unzip();
+    if let Some(path) = transitions_path {
+        exit_on_write_error(write_transitions(&path, &transition_matrix(&vecs, window.sentence_lens)), "transitions", &path);
+        info!("Store transitions to {}", path.display());
+    }
+    if find_longest_repeat {
+        match longest_repeat(&vecs, window.sentence_lens) {
+            Some((start, len)) => println!("Longest repeated sequence is {} characters at {}: {}", len, start, decode_ngram(&vecs[start..(start + len)], &report_option.rev_map)),
+            None => println!("No character sequence is repeated")
+        }
+    }
+    let test_vecs: Option<Vec<Id>> = test_vectorized.as_ref().map(|test| test.tagged.iter().map(|(v, _)| *v).collect());
+    let test_window = test_vectorized.as_ref().map(|test| WindowOption {
+        stride,
+        sentence_lens: boundaries.segment_lens(&test.sentence_lens, &test.document_lens)
+    });
+    let vocab = *v.read().unwrap() as usize;
+    let progress = spinner("Reporting n-gram", quiet);
+    // each gram is reported on its own task with its own output files
+    // and its log lines are printed in gram order once every task is done
+    // gram that is not started before interrupted is skipped
+    let results: Vec<Option<(std::io::Result<NgramSummary>, Vec<ReportLine>)>> = grams.par_iter().cloned().enumerate().map(|(i, gram)| {
+        if interrupted.load(Ordering::SeqCst) {
+            return None
+        }
+        let mut log = vec![];
+        let mut report_option = report_option.clone();
+        report_option.out_path = match &out_dir {
+            Some(dir) => dir.join(format!("ngram_{}.{}", gram, format.extension())),
+            None if grams.len() > 1 => suffixed_path(Path::new(out_path), &format!("{}gram", gram)),
+            None => PathBuf::from(out_path)
+        };
+        // explicit --out store the primary n-gram table alongside out dir
+        // unless it is sharded, in which case shards in out dir are the only table
+        if i == 0 && out_dir.is_some() && matches.occurrences_of("output file") > 0 && !report_option.shard_by_prefix {
+            report_option.copy_path = Some(PathBuf::from(out_path));
+        }
+        report_option.coverage_path = out_dir.as_ref().map(|dir| dir.join(format!("coverage_{}.csv", gram)));
+        report_option.fof_path = match matches.value_of("fof out") {
+            Some(path) if grams.len() > 1 => Some(suffixed_path(Path::new(path), &format!("{}gram", gram))),
+            explicit => artifact_path(explicit, &format!("fof_{}.csv", gram))
+        };
+        let mut report = || -> std::io::Result<NgramSummary> {
+            let mut summary = match &ngrams[i].table {
+                NgramTable::Tagged(counted) => report_ngrams(gram, counted, ngrams[i].count_times, &report_option, &mut log)?,
+                NgramTable::Chars(counted) => report_ngrams(gram, counted, ngrams[i].count_times, &report_option, &mut log)?
+            };
+            if with_tags {
+                if boundary_split {
+                    analyze_boundary_split(gram, &tagged_chars, &labels, &window, &report_option, &mut log)?;
+                }
+                if positional {
+                    analyze_positional(gram, &tagged_chars, &sentence_lens, stride, &report_option, &mut log)?;
+                }
+                if per_file {
+                    analyze_per_file(gram, &tagged_chars, &window, &file_stats, &report_option, &mut log)?;
+                }
+            } else {
+                if boundary_split {
+                    analyze_boundary_split(gram, &vecs, &labels, &window, &report_option, &mut log)?;
+                }
+                if positional {
+                    analyze_positional(gram, &vecs, &sentence_lens, stride, &report_option, &mut log)?;
+                }
+                if per_file {
+                    analyze_per_file(gram, &vecs, &window, &file_stats, &report_option, &mut log)?;
+                }
+            }
+            summary.exceeds_sentences = gram_exceeds_sentences(gram, &window);
+            if let Some(longest) = summary.exceeds_sentences {
+                log.push(ReportLine::Log(Level::Warn, format!("{}-gram is longer than the longest {} of {} characters so no {}-gram is counted", gram, boundaries, longest, gram)));
+            }
+            if let (Some(test_vectorized), Some(test_vecs), Some(test_window)) = (&test_vectorized, &test_vecs, &test_window) {
+                summary.perplexity = if with_tags {
+                    perplexity(gram, &tagged_chars, &window, &test_vectorized.tagged, test_window, vocab)
+                } else {
+                    perplexity(gram, &vecs, &window, test_vecs, test_window, vocab)
+                };
+                match summary.perplexity {
+                    Some(p) => log.push(ReportLine::Result(format!("{}-gram perplexity of test corpus is {}", gram, p))),
+                    None => log.push(ReportLine::Result(format!("Test corpus has no {}-gram", gram)))
+                }
+            }
+
+            Ok(summary)
+        };
+        let summary = report();
+
+        Some((summary, log))
+    }).collect();
+    progress.finish_and_clear();
+    // every gram is reported before aborting so output of gram that succeeded is still described
+    let mut failure = None;
+    let mut summaries: Vec<NgramSummary> = vec![];
+    for (summary, log) in results.into_iter().flatten() {
+        log.iter().for_each(ReportLine::emit);
+        match summary {
+            Ok(summary) => summaries.push(summary),
+            Err(err) => failure = failure.or(Some(err))
+        }
+    }
+    if let Some(err) = failure {
+        exit_with(RunError::Io(err.to_string()));
+    }
+
+    let stages = stage_times(expand_time, &file_stats, &summaries);
+    print_stage_times(&stages);
+    if let Some(path) = report_path {
+        exit_on_write_error(write_report(&path, &file_stats, unique_chars, parse_time, &stages, &RunSettings {sample, boundaries}, &summaries), "report", &path);
+        info!("Store report to {}", path.display());
+    }
+    let ngram_bytes = summaries.iter().map(|s| s.buffer_bytes).max().unwrap_or(0);
+    info!(
+        "Vectorized corpus: {}, n-gram buffer: {}, char map: {}",
+        format_bytes(vec_bytes(&tagged_chars)),
+        format_bytes(ngram_bytes),
+        format_bytes(char_map_bytes(report_option.rev_map.len()))
+    );
+    if let Some(bytes) = peak_rss() {
+        info!("Peak memory: {}", format_bytes(bytes));
+    }
+    if interrupted.load(Ordering::SeqCst) {
+        exit_with(RunError::Interrupted);
+    }
+    if let Err(err) = run_outcome(corpuses.len(), &file_stats, &summaries) {
+        exit_with(err);
+    }
 }
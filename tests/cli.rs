@@ -0,0 +1,208 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+/// Empty temporary directory unique to this process and `name`.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("best_analysis_cli_{}_{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Write a JSON corpus where every word is tagged 1 into `name` in `dir`.
+/// Each document is a list of sentences and each sentence is a list of words.
+fn write_corpus(dir: &Path, name: &str, docs: &[&[&[&str]]]) -> PathBuf {
+    let json: Vec<Vec<Vec<serde_json::Value>>> = docs.iter().map(|doc| doc.iter().map(|sentence| sentence.iter().map(|word| {
+        serde_json::json!([word.chars().map(|c| c.to_string()).collect::<Vec<String>>(), 1])
+    }).collect()).collect()).collect();
+    let path = dir.join(name);
+    std::fs::write(&path, serde_json::to_string(&json).unwrap()).unwrap();
+    path
+}
+
+/// Run the command line tool with `args` in `dir`. Standard input is empty.
+fn run(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_best_analysis"))
+        .current_dir(dir)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+/// Run the command line tool with `args` in `dir` while `input` is piped to its standard input.
+fn run_piped(dir: &Path, args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_best_analysis"))
+        .current_dir(dir)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// Decoded n-gram and count of each row of CSV n-gram table written with `--with-chars`.
+/// Rows are sorted since vectorized id depends on which file is read first.
+fn ngram_rows(path: &Path) -> Vec<(String, String)> {
+    let content = std::fs::read_to_string(path).unwrap();
+    let mut rows: Vec<(String, String)> = content.lines().skip(1).map(|line| {
+        let columns: Vec<&str> = line.split(',').collect();
+        (columns[1].to_owned(), columns[2].to_owned())
+    }).collect();
+    rows.sort();
+    rows
+}
+
+#[test]
+fn single_thread_match_multi_thread() {
+    let dir = temp_dir("threads");
+    write_corpus(&dir, "a.json", &[&[&["กข", "ค"], &["ขค"]], &[&["คง"]]]);
+    write_corpus(&dir, "b.json", &[&[&["งก", "ข"]]]);
+    write_corpus(&dir, "c.json", &[&[&["กขค"]]]);
+    for threads in &["1", "4"] {
+        let output = run(&dir, &["-g", "1", "2", "3", "-s", "*.json", "--with-chars", "-t", threads, "--out-dir", &format!("out_{}", threads)]);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+    for gram in 1..=3 {
+        let name = format!("ngram_{}.csv", gram);
+        let single = ngram_rows(&dir.join("out_1").join(&name));
+        assert!(!single.is_empty());
+        assert_eq!(single, ngram_rows(&dir.join("out_4").join(&name)));
+    }
+}
+
+#[test]
+fn piped_corpus_match_corpus_file() {
+    let dir = temp_dir("piped");
+    let corpus = write_corpus(&dir, "a.json", &[&[&["กข", "ค"], &["ขค"]], &[&["คง"]]]);
+    let output = run(&dir, &["-g", "2", "-s", "a.json", "--with-chars", "-o", "file.csv"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let output = run_piped(&dir, &["-g", "2", "-s", "-", "--with-chars", "-o", "piped.csv"], &std::fs::read(corpus).unwrap());
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let piped = ngram_rows(&dir.join("piped.csv"));
+    assert!(!piped.is_empty());
+    assert_eq!(piped, ngram_rows(&dir.join("file.csv")));
+}
+
+#[test]
+fn bad_char_list_line_is_fatal() {
+    let dir = temp_dir("char_list");
+    write_corpus(&dir, "a.json", &[&[&["กข"]]]);
+    std::fs::write(dir.join("chars.txt"), "# digits\n0-9\nz-a\n").unwrap();
+    let output = run(&dir, &["-g", "1", "-s", "a.json", "--char-list-file", "chars.txt"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("chars.txt at line 3"));
+    let output = run(&dir, &["-g", "1", "-s", "a.json", "--char-list-file", "missing.txt"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("missing.txt"));
+}
+
+#[test]
+fn uncreatable_out_dir_is_io_error() {
+    let dir = temp_dir("out_dir");
+    write_corpus(&dir, "a.json", &[&[&["กข"]]]);
+    std::fs::write(dir.join("taken"), "").unwrap();
+    let output = run(&dir, &["-g", "1", "-s", "a.json", "--out-dir", "taken/out"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("taken/out"));
+}
+
+#[test]
+fn dry_run_write_nothing() {
+    let dir = temp_dir("dry_run");
+    write_corpus(&dir, "a.json", &[&[&["กข", "ค"]]]);
+    let output = run(&dir, &["-g", "1", "2", "-s", "a.json", "--dry-run", "--out-dir", "out", "-o", "ngram.csv", "--report", "report.json"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("All 1 corpus files can be parsed"));
+    let files: Vec<PathBuf> = std::fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+    assert_eq!(files, vec![dir.join("a.json")]);
+}
+
+#[test]
+fn bad_tag_map_line_is_fatal() {
+    let dir = temp_dir("tag_map");
+    write_corpus(&dir, "a.json", &[&[&["กข"]]]);
+    std::fs::write(dir.join("tags.txt"), "# merge\n5=4\n3\n").unwrap();
+    let output = run(&dir, &["-g", "1", "-s", "a.json", "--tag-map", "tags.txt"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("tags.txt at line 3"));
+    let output = run(&dir, &["-g", "1", "-s", "a.json", "--tag-map", "missing.txt"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("missing.txt"));
+}
+
+#[test]
+fn concurrent_grams_write_complete_tables() {
+    let dir = temp_dir("concurrent_grams");
+    write_corpus(&dir, "a.json", &[&[&["กข", "ค"], &["ขคง", "ก"]], &[&["คงกข"]], &[&["งกขค", "ข"]]]);
+    let output = run(&dir, &["-g", "1", "2", "3", "-s", "a.json", "-t", "3", "--out-dir", "out"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let positions: Vec<usize> = (1..=3).map(|gram| stdout.find(&format!("unique {}-gram", gram)).unwrap()).collect();
+    assert!(positions.windows(2).all(|p| p[0] < p[1]), "{}", stdout);
+    for gram in 1..=3 {
+        let gram = gram.to_string();
+        let expected = format!("{}.csv", gram);
+        let output = run(&dir, &["-g", &gram, "-s", "a.json", "-t", "1", "-o", &expected]);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let table = std::fs::read_to_string(dir.join("out").join(format!("ngram_{}.csv", gram))).unwrap();
+        assert!(table.lines().count() > 1);
+        assert_eq!(table, std::fs::read_to_string(dir.join(expected)).unwrap());
+    }
+}
+
+#[test]
+fn src_list_file_add_every_listed_file() {
+    let dir = temp_dir("src_list");
+    write_corpus(&dir, "a.json", &[&[&["กข"]]]);
+    write_corpus(&dir, "b.json", &[&[&["คง"]]]);
+    std::fs::write(dir.join("list.txt"), "# corpus\na.json\n\nb.json\n").unwrap();
+    let output = run(&dir, &["-g", "1", "--src-list", "list.txt", "--stats-out", "stats.csv", "-o", "out.csv"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stats = std::fs::read_to_string(dir.join("stats.csv")).unwrap();
+    assert!(stats.contains("a.json") && stats.contains("b.json"), "{}", stats);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Total 4 unique 1-gram"));
+    let output = run(&dir, &["-g", "1", "--src-list", "missing.txt"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("missing.txt"));
+}
+
+#[test]
+fn no_ngram_write_only_corpus_statistics() {
+    let dir = temp_dir("no_ngram");
+    write_corpus(&dir, "a.json", &[&[&["กข", "ค"]]]);
+    let output = run(&dir, &["--no-ngram", "-s", "a.json", "--map-out", "map.csv", "--char-freq-out", "freq.csv", "--fof-out", "fof.csv"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(std::fs::read_to_string(dir.join("map.csv")).unwrap().lines().count() > 1);
+    assert!(std::fs::read_to_string(dir.join("freq.csv")).unwrap().lines().count() > 1);
+    assert!(!dir.join("out.csv").exists() && !dir.join("fof.csv").exists());
+    let output = run(&dir, &["--no-ngram", "-s", "a.json", "--out-dir", "out"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let mut files: Vec<String> = std::fs::read_dir(dir.join("out")).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect();
+    files.sort();
+    assert!(files.contains(&"char_map.csv".to_owned()) && files.contains(&"char_freq.csv".to_owned()), "{:?}", files);
+    assert!(files.iter().all(|f| !f.starts_with("ngram_") && !f.starts_with("fof_")), "{:?}", files);
+}
+
+#[test]
+fn diagnostics_go_to_stderr() {
+    let dir = temp_dir("streams");
+    write_corpus(&dir, "a.json", &[&[&["กข", "ค"]]]);
+    let output = run(&dir, &["-g", "2", "-s", "a.json", "-v"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stdout.contains("Total 3 characters in corpus") && stdout.contains("Total 2 unique 2-gram"), "{}", stdout);
+    assert!(!stdout.contains("Parsing:") && !stdout.contains("Store 2-gram"), "{}", stdout);
+    assert!(stderr.contains("Parsing:") && stderr.contains("Store 2-gram"), "{}", stderr);
+    assert!(!stderr.contains("Total 3 characters in corpus"), "{}", stderr);
+
+    let output = run(&dir, &["-g", "2", "-s", "a.json", "-q", "-o", "quiet.csv"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(output.stderr.is_empty(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Total 2 unique 2-gram"));
+}